@@ -24,7 +24,7 @@ use anyhow::Result;
 #[derive(Component, Clone)]
 struct Request;
 
-#[derive(Component, Clone, Default, Debug)]
+#[derive(Component, Clone, Debug)]
 struct Reply(bool);
 
 fn main() {
@@ -75,10 +75,7 @@ fn interaction_panel(mut contexts: EguiContexts, mut query_event_writer: EventWr
             ui.label("Interaction");
             ui.horizontal(|ui| {
                 if ui.button("Send request to google").clicked() {
-                    query_event_writer.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Request,
-                    });
+                    query_event_writer.send(QueryEvent::new(uuid::Uuid::new_v4(), Request));
                 }
             });
             ui.separator();
@@ -0,0 +1,78 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use anyhow::Result;
+use bevy::prelude::*;
+use bevy_query_service::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Request(i32);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Reply(i32);
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> Result<Self> {
+        Ok(Reply(request.request.0 * 2))
+    }
+}
+
+fn main() {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins);
+    app.add_plugins(bevy::log::LogPlugin::default());
+    app.add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default());
+
+    app.add_plugins(QueryServicePlugin);
+    app.add_plugins(QueryRemoteServerPlugin::new("127.0.0.1:0").with_service::<Request, Reply>("double"));
+    app.add_systems(Update, run_query_server::<Request, Reply>);
+    app.add_systems(Update, cleanup_requests);
+
+    let address = loop {
+        app.update();
+        if let Some(address) = app.world().get_resource::<QueryRemoteServerAddress>() {
+            break address.0;
+        }
+    };
+
+    // A loopback client, standing in for another process
+    let client = std::thread::spawn(move || {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async move {
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            let request = RemoteQueryRequest {
+                uuid: uuid::Uuid::new_v4(),
+                service: "double".into(),
+                request: serde_json::json!(21),
+            };
+            write_frame(&mut stream, &serde_json::to_vec(&request).unwrap()).await.unwrap();
+
+            let frame = read_frame(&mut stream).await.unwrap().unwrap();
+            let response: RemoteQueryResponse = serde_json::from_slice(&frame).unwrap();
+            info!("[{:?}]: Remote reply: {:?}, error: {:?}", response.uuid, response.reply, response.error);
+        });
+    });
+
+    while !client.is_finished() {
+        app.update();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
@@ -40,7 +40,7 @@ enum Fruit {
     Orange,
 }
 
-#[derive(Component, Clone, Default, Debug)]
+#[derive(Component, Clone, Debug)]
 struct Reply(i32);
 
 fn main() {
@@ -107,22 +107,13 @@ fn interaction_panel(mut contexts: EguiContexts, mut query_event_writer: EventWr
             ui.label("Interaction");
             ui.horizontal(|ui| {
                 if ui.button("Send apple request").clicked() {
-                    query_event_writer.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Request(Fruit::Apple),
-                    });
+                    query_event_writer.send(QueryEvent::new(uuid::Uuid::new_v4(), Request(Fruit::Apple)));
                 }
                 if ui.button("Send banana request").clicked() {
-                    query_event_writer.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Request(Fruit::Banana),
-                    });
+                    query_event_writer.send(QueryEvent::new(uuid::Uuid::new_v4(), Request(Fruit::Banana)));
                 }
                 if ui.button("Send oranage request").clicked() {
-                    query_event_writer.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Request(Fruit::Orange),
-                    });
+                    query_event_writer.send(QueryEvent::new(uuid::Uuid::new_v4(), Request(Fruit::Orange)));
                 }
             });
             ui.separator();
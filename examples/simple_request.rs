@@ -24,7 +24,7 @@ use anyhow::Result;
 #[derive(Component, Clone)]
 struct Ping;
 
-#[derive(Component, Clone, Default, Debug)]
+#[derive(Component, Clone, Debug)]
 struct Pong(bool);
 
 impl QueryServerOps<Ping> for Pong {
@@ -58,10 +58,7 @@ fn interaction_panel(mut contexts: EguiContexts, mut query_event_writer: EventWr
             ui.label("Interaction");
             ui.horizontal(|ui| {
                 if ui.button("Send query request").clicked() {
-                    query_event_writer.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Ping,
-                    });
+                    query_event_writer.send(QueryEvent::new(uuid::Uuid::new_v4(), Ping));
                 }
             });
             ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
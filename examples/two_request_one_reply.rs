@@ -27,7 +27,7 @@ struct Question1;
 #[derive(Component, Clone)]
 struct Question2;
 
-#[derive(Component, Clone, Default, Debug)]
+#[derive(Component, Clone, Debug)]
 struct Answer(String);
 
 impl QueryServerOps<Question1> for Answer {
@@ -70,16 +70,10 @@ fn interaction_panel(mut contexts: EguiContexts, mut question_1_event: EventWrit
             ui.label("Interaction");
             ui.horizontal(|ui| {
                 if ui.button("Asking question 1").clicked() {
-                    question_1_event.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Question1,
-                    });
+                    question_1_event.send(QueryEvent::new(uuid::Uuid::new_v4(), Question1));
                 }
                 if ui.button("Asking question 2").clicked() {
-                    question_2_event.send(QueryEvent {
-                        uuid: uuid::Uuid::new_v4(),
-                        request: Question2,
-                    });
+                    question_2_event.send(QueryEvent::new(uuid::Uuid::new_v4(), Question2));
                 }
             });
             ui.allocate_rect(ui.available_rect_before_wrap(), egui::Sense::hover());
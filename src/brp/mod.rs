@@ -0,0 +1,223 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use bevy_ecs::reflect::AppTypeRegistry;
+use bevy_ecs::world::DeferredWorld;
+use bevy_reflect::serde::{TypedReflectDeserializer, TypedReflectSerializer};
+use bevy_reflect::{FromReflect, GetTypeRegistration, Reflect};
+use bevy_remote::{error_codes, BrpError, BrpResult, RemoteLast, RemotePlugin, RemoteSet};
+use serde::de::DeserializeSeed;
+use std::collections::HashMap;
+
+/// Prefix of the BRP method of every service
+pub const QUERY_BRP_METHOD_PREFIX: &str = "query/";
+
+/// Error code of a call whose goal failed, was rejected or was cancelled
+pub const QUERY_BRP_FAILED: i16 = -32000;
+
+/// Registers query services as methods of `bevy_remote`, served on its own endpoint
+pub trait QueryRemotePluginExt {
+    /// Exposes the service serving `T` with `U` as the method `query/<name>`
+    /// Requests and replies are converted through their `Reflect` type info, and both types are registered on the first call
+    fn with_query_service<T, U>(self, name: impl Into<String>) -> Self
+    where
+        T: FromReflect + GetTypeRegistration + Send + Sync + 'static,
+        U: Reflect + GetTypeRegistration + Send + Sync + 'static;
+}
+
+impl QueryRemotePluginExt for RemotePlugin {
+    fn with_query_service<T, U>(self, name: impl Into<String>) -> Self
+    where
+        T: FromReflect + GetTypeRegistration + Send + Sync + 'static,
+        U: Reflect + GetTypeRegistration + Send + Sync + 'static,
+    {
+        let method = format!("{}{}", QUERY_BRP_METHOD_PREFIX, name.into());
+        self.with_watching_method(method.clone(), move |In(params): In<Option<serde_json::Value>>, world: &mut World| {
+            serve_brp_query::<T, U>(world, &method, params)
+        })
+    }
+}
+
+struct QueryBrpCall {
+    entity: Entity,
+    reply: fn(&World, Entity) -> Option<Result<serde_json::Value, String>>,
+    is_polled: bool,
+    /// Kept for the frame after the answer, when `bevy_remote` may poll the call once more
+    outcome: Option<Result<serde_json::Value, String>>,
+}
+
+/// The BRP calls in flight, by method and params
+/// Identical calls made at the same time are served by the same goal
+#[derive(Resource, Default)]
+pub struct QueryBrpCalls {
+    calls: HashMap<(String, String), QueryBrpCall>,
+}
+
+impl QueryBrpCalls {
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+}
+
+/// Tracks the goals of the `query/` methods registered with `QueryRemotePluginExt`
+/// Goals of calls whose client went away are cancelled
+/// Requires the `RemotePlugin` of `bevy_remote`
+#[derive(Default)]
+pub struct QueryBrpPlugin;
+
+impl Plugin for QueryBrpPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<QueryBrpCalls>();
+        app.add_systems(RemoteLast, sweep_brp_calls.after(RemoteSet::ProcessRequests));
+        app.add_observer(keep_removed_brp_outcome);
+    }
+}
+
+/// Polled by `bevy_remote` every frame until it returns the outcome of the goal
+fn serve_brp_query<T, U>(world: &mut World, method: &str, params: Option<serde_json::Value>) -> BrpResult<Option<serde_json::Value>>
+where
+    T: FromReflect + GetTypeRegistration + Send + Sync + 'static,
+    U: Reflect + GetTypeRegistration + Send + Sync + 'static,
+{
+    let params = params.unwrap_or_default();
+    let key = (method.to_string(), params.to_string());
+    let Some(mut calls) = world.get_resource_mut::<QueryBrpCalls>() else {
+        return Err(BrpError::internal("QueryBrpPlugin is not added"));
+    };
+
+    let entity = match calls.calls.get_mut(&key) {
+        Some(QueryBrpCall { outcome: Some(outcome), .. }) => return get_brp_result(outcome.clone()),
+        Some(call) => {
+            call.is_polled = true;
+            call.entity
+        }
+        None => {
+            register_brp_types::<T, U>(world);
+            let entity = spawn_reflected_request::<T, U>(world, uuid::Uuid::new_v4(), params).map_err(|e| BrpError {
+                code: error_codes::INVALID_PARAMS,
+                message: e.to_string(),
+                data: None,
+            })?;
+            world.resource_mut::<QueryBrpCalls>().calls.insert(
+                key,
+                QueryBrpCall {
+                    entity,
+                    reply: get_reflected_reply::<U>,
+                    is_polled: true,
+                    outcome: None,
+                },
+            );
+            return Ok(None);
+        }
+    };
+
+    let Some(outcome) = get_reflected_reply::<U>(world, entity) else {
+        return Ok(None);
+    };
+
+    if let Some(call) = world.resource_mut::<QueryBrpCalls>().calls.get_mut(&key) {
+        call.outcome = Some(outcome.clone());
+    }
+    get_brp_result(outcome)
+}
+
+fn get_brp_result(outcome: Result<serde_json::Value, String>) -> BrpResult<Option<serde_json::Value>> {
+    outcome.map(Some).map_err(|message| BrpError {
+        code: QUERY_BRP_FAILED,
+        message,
+        data: None,
+    })
+}
+
+/// Forgets answered calls after a frame, and cancels the goals of unanswered calls `bevy_remote` stopped polling
+pub fn sweep_brp_calls(mut calls: ResMut<QueryBrpCalls>, mut goals: Query<&mut GoalComponent>) {
+    calls.calls.retain(|_, call| {
+        let is_polled = std::mem::take(&mut call.is_polled);
+        if !is_polled && call.outcome.is_none() {
+            if let Ok(mut goal) = goals.get_mut(call.entity) {
+                goal.mark_cancelled();
+            }
+        }
+        is_polled
+    });
+}
+
+/// Keeps the outcome of a goal despawned before `bevy_remote` polled it again
+/// The goal components are still readable while it is being removed
+pub fn keep_removed_brp_outcome(trigger: Trigger<OnRemove, GoalComponent>, mut world: DeferredWorld) {
+    let entity = trigger.entity();
+    let reply = world
+        .get_resource::<QueryBrpCalls>()
+        .and_then(|calls| calls.calls.values().find(|call| call.entity == entity && call.outcome.is_none()))
+        .map(|call| call.reply);
+    let Some(reply) = reply else {
+        return;
+    };
+
+    let outcome = reply(&world, entity).unwrap_or_else(|| Err("Request was despawned before completion".to_string()));
+    if let Some(mut calls) = world.get_resource_mut::<QueryBrpCalls>() {
+        for call in calls.calls.values_mut().filter(|call| call.entity == entity) {
+            call.outcome = Some(outcome.clone());
+        }
+    }
+}
+
+fn register_brp_types<T, U>(world: &mut World)
+where
+    T: GetTypeRegistration,
+    U: GetTypeRegistration,
+{
+    let registry = world.get_resource_or_init::<AppTypeRegistry>().clone();
+    let mut registry = registry.write();
+    registry.register::<T>();
+    registry.register::<U>();
+}
+
+fn spawn_reflected_request<T, U>(world: &mut World, uuid: uuid::Uuid, request: serde_json::Value) -> Result<Entity>
+where
+    T: FromReflect + Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let request = {
+        let registry = world.resource::<AppTypeRegistry>().read();
+        let Some(registration) = registry.get(std::any::TypeId::of::<T>()) else {
+            return Err(anyhow::anyhow!("{} is not registered", std::any::type_name::<T>()));
+        };
+
+        let reflected = TypedReflectDeserializer::new(registration, &registry).deserialize(request)?;
+        T::from_reflect(reflected.as_ref()).ok_or_else(|| anyhow::anyhow!("Request is not a {}", std::any::type_name::<T>()))?
+    };
+
+    spawn_request::<T, U>(world, QueryEvent::new(uuid, request).with_reply::<U>()).ok_or_else(|| anyhow::anyhow!("Request was not accepted"))
+}
+
+fn get_reflected_reply<U>(world: &World, entity: Entity) -> Option<Result<serde_json::Value, String>>
+where
+    U: Reflect + Send + Sync + 'static,
+{
+    get_goal_outcome::<U>(world, entity).map(|outcome| {
+        outcome.and_then(|reply| {
+            let registry = world.resource::<AppTypeRegistry>().read();
+            serde_json::to_value(TypedReflectSerializer::new(reply.as_partial_reflect(), &registry)).map_err(|e| e.to_string())
+        })
+    })
+}
@@ -0,0 +1,98 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A wire format for remote transports, chosen per service and negotiated per connection
+pub trait QueryCodec: Send + Sync + 'static {
+    /// The name announced in the handshake
+    const NAME: &'static str;
+
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>>;
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V>;
+}
+
+/// Human-readable JSON, always supported
+pub struct QueryJsonCodec;
+
+impl QueryCodec for QueryJsonCodec {
+    const NAME: &'static str = "json";
+
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// MessagePack, with struct fields encoded by name
+#[cfg(feature = "msgpack")]
+pub struct QueryMessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl QueryCodec for QueryMessagePackCodec {
+    const NAME: &'static str = "msgpack";
+
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// Bincode, the most compact but not self-describing
+/// Both ends must agree on the exact request and reply types
+#[cfg(feature = "bincode")]
+pub struct QueryBincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl QueryCodec for QueryBincodeCodec {
+    const NAME: &'static str = "bincode";
+
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// CBOR
+#[cfg(feature = "cbor")]
+pub struct QueryCborCodec;
+
+#[cfg(feature = "cbor")]
+impl QueryCodec for QueryCborCodec {
+    const NAME: &'static str = "cbor";
+
+    fn encode<V: Serialize>(value: &V) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode<V: DeserializeOwned>(bytes: &[u8]) -> Result<V> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
@@ -0,0 +1,148 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Path prefix of the gateway, followed by the service name
+pub const QUERY_HTTP_PATH: &str = "/query/";
+
+/// Header carrying the uuid of the goal that served an HTTP query
+pub const QUERY_HTTP_UUID_HEADER: &str = "x-query-uuid";
+
+/// The address the HTTP gateway is listening on, inserted once the socket is bound
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QueryHttpGatewayAddress(pub std::net::SocketAddr);
+
+/// Exposes query services over HTTP, mapping `POST /query/{service}` to a request of `service`
+/// The body is the JSON request content and the response is the JSON reply content, or `{"error": ...}`
+/// Requires the `TokioTasksPlugin`
+pub struct QueryHttpGatewayPlugin {
+    address: String,
+    registrations: Vec<RemoteRegistration>,
+}
+
+impl QueryHttpGatewayPlugin {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`
+    pub fn with_service<T, U>(mut self, name: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register::<T, U>(name.clone());
+        }));
+        self
+    }
+}
+
+impl Plugin for QueryHttpGatewayPlugin {
+    fn build(&self, app: &mut App) {
+        let transport = add_remote_services(app, &self.registrations);
+
+        let address = self.address.clone();
+        app.add_systems(Startup, move |runtime: Res<TokioTasksRuntime>| {
+            let address = address.clone();
+            runtime.spawn_background_task(move |ctx| run_http_gateway(ctx, transport, address));
+        });
+    }
+}
+
+async fn run_http_gateway(ctx: TaskContext, transport: QueryRemoteTransport, address: String) {
+    serve_tcp_connections(ctx, "HTTP gateway", address, QueryHttpGatewayAddress, move |ctx, stream| handle_http_connection(ctx, transport, stream)).await;
+}
+
+async fn handle_http_connection(ctx: TaskContext, transport: QueryRemoteTransport, stream: tokio::net::TcpStream) {
+    let service = hyper::service::service_fn(move |request| handle_http_request(ctx.clone(), transport, request));
+    let connection = hyper::server::conn::http1::Builder::new().serve_connection(hyper_util::rt::TokioIo::new(stream), service);
+    if let Err(e) = connection.await {
+        debug!("HTTP connection closed: {}", e);
+    }
+}
+
+/// Reads a whole request body, bounded by `MAX_FRAME_LENGTH`
+pub(crate) async fn read_http_body(request: Request<Incoming>) -> Result<Bytes, String> {
+    match Limited::new(request.into_body(), MAX_FRAME_LENGTH as usize).collect().await {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) => Err(format!("Failed to read the request body: {}", e)),
+    }
+}
+
+async fn handle_http_request(mut ctx: TaskContext, transport: QueryRemoteTransport, request: Request<Incoming>) -> Result<Response<Full<Bytes>>, std::convert::Infallible> {
+    let Some(service) = request.uri().path().strip_prefix(QUERY_HTTP_PATH).map(|service| service.to_string()) else {
+        return Ok(http_error(StatusCode::NOT_FOUND, "Unknown path"));
+    };
+
+    if request.method() != Method::POST {
+        return Ok(http_error(StatusCode::METHOD_NOT_ALLOWED, "Queries must be sent with POST"));
+    }
+
+    let body = match read_http_body(request).await {
+        Ok(body) => body,
+        Err(e) => return Ok(http_error(StatusCode::BAD_REQUEST, e)),
+    };
+
+    let request = match serde_json::from_slice::<serde_json::Value>(&body) {
+        Ok(request) => request,
+        Err(e) => return Ok(http_error(StatusCode::BAD_REQUEST, format!("Invalid request body: {}", e))),
+    };
+
+    let name = service.clone();
+    let is_known = ctx.run_on_main_thread(move |ctx| contains_remote_service(ctx.world, transport, &name)).await;
+    if !is_known {
+        return Ok(http_error(StatusCode::NOT_FOUND, format!("Unknown service: {}", service)));
+    }
+
+    let uuid = uuid::Uuid::new_v4();
+    debug!("[{:?}]: HTTP query for {}", uuid, service);
+    let response = submit_remote_query(&mut ctx, transport, RemoteQueryRequest { uuid, service, request }).await;
+    let mut response = match response.reply {
+        Some(reply) => http_json(StatusCode::OK, &reply),
+        None => {
+            let error = response.error.unwrap_or_else(|| "Query failed".to_string());
+            http_json(StatusCode::INTERNAL_SERVER_ERROR, &serde_json::json!({ "error": error }))
+        }
+    };
+
+    if let Ok(value) = hyper::header::HeaderValue::from_str(&uuid.to_string()) {
+        response.headers_mut().insert(QUERY_HTTP_UUID_HEADER, value);
+    }
+    Ok(response)
+}
+
+pub(crate) fn http_error(status: StatusCode, error: impl Into<String>) -> Response<Full<Bytes>> {
+    http_json(status, &serde_json::json!({ "error": error.into() }))
+}
+
+pub(crate) fn http_json(status: StatusCode, body: &serde_json::Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response.headers_mut().insert(hyper::header::CONTENT_TYPE, hyper::header::HeaderValue::from_static("application/json"));
+    response
+}
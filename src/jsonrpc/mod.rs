@@ -0,0 +1,363 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// The error object of a failed JSON-RPC call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryJsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl QueryJsonRpcError {
+    pub const PARSE_ERROR: i64 = -32700;
+    pub const INVALID_REQUEST: i64 = -32600;
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The goal of the call failed, was rejected or was cancelled
+    pub const QUERY_FAILED: i64 = -32000;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+/// The response to a JSON-RPC call, carrying either the reply content as `result` or an `error`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryJsonRpcResponse {
+    pub jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<QueryJsonRpcError>,
+    pub id: serde_json::Value,
+}
+
+impl QueryJsonRpcResponse {
+    pub fn success(id: serde_json::Value, result: serde_json::Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    pub fn failure(id: serde_json::Value, error: QueryJsonRpcError) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            result: None,
+            error: Some(error),
+            id,
+        }
+    }
+}
+
+/// A JSON-RPC call waiting for the goal of the same uuid
+#[derive(Debug, Clone)]
+pub struct QueryJsonRpcCall {
+    /// Identifies the connection, or the HTTP request for BRP, the call was read from
+    pub connection: uuid::Uuid,
+    pub id: serde_json::Value,
+    pub method: String,
+}
+
+/// The JSON-RPC calls in flight, by the uuid of the goal serving them
+/// A call is removed once it is answered, and notifications are not recorded
+#[derive(Resource, Default)]
+pub struct QueryJsonRpcCalls {
+    calls: HashMap<uuid::Uuid, QueryJsonRpcCall>,
+}
+
+impl QueryJsonRpcCalls {
+    pub fn get_call(&self, uuid: uuid::Uuid) -> Option<&QueryJsonRpcCall> {
+        self.calls.get(&uuid)
+    }
+
+    /// The uuid of the goal serving the call `id` of `connection`
+    pub fn get_uuid(&self, connection: uuid::Uuid, id: &serde_json::Value) -> Option<uuid::Uuid> {
+        self.calls.iter().find(|(_, call)| call.connection == connection && call.id == *id).map(|(uuid, _)| *uuid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+}
+
+/// Where the JSON-RPC adapter reads calls from
+#[derive(Debug, Clone)]
+pub enum QueryJsonRpcTransport {
+    Stdio,
+    Socket(QueryRemoteAddress),
+}
+
+/// The address the JSON-RPC adapter is listening on, inserted once a TCP socket is bound
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QueryJsonRpcAddress(pub std::net::SocketAddr);
+
+/// Serves query services as JSON-RPC 2.0 methods, one JSON message per line
+/// The `params` of a call are the request content and its `result` is the reply content
+/// Batch calls are served concurrently, and notifications are served without a response
+/// Requires the `TokioTasksPlugin`
+pub struct QueryJsonRpcPlugin {
+    transport: QueryJsonRpcTransport,
+    registrations: Vec<RemoteRegistration>,
+}
+
+impl QueryJsonRpcPlugin {
+    /// Serves calls over a TCP or Unix socket
+    pub fn new(address: impl Into<QueryRemoteAddress>) -> Self {
+        Self {
+            transport: QueryJsonRpcTransport::Socket(address.into()),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Serves calls read from stdin, answering on stdout
+    /// Logs must then go to stderr only
+    pub fn stdio() -> Self {
+        Self {
+            transport: QueryJsonRpcTransport::Stdio,
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Exposes the service serving `T` with `U` as the method `method`
+    pub fn with_method<T, U>(mut self, method: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+    {
+        let method = method.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register::<T, U>(method.clone());
+        }));
+        self
+    }
+}
+
+impl Plugin for QueryJsonRpcPlugin {
+    fn build(&self, app: &mut App) {
+        let transport = add_remote_services(app, &self.registrations);
+
+        let listener = self.transport.clone();
+        app.add_systems(Startup, move |runtime: Res<TokioTasksRuntime>| {
+            let listener = listener.clone();
+            runtime.spawn_background_task(move |ctx| async move {
+                match listener {
+                    QueryJsonRpcTransport::Stdio => handle_jsonrpc_connection(ctx, transport, tokio::io::stdin(), tokio::io::stdout()).await,
+                    QueryJsonRpcTransport::Socket(QueryRemoteAddress::Tcp(address)) => run_jsonrpc_tcp_server(ctx, transport, address).await,
+                    #[cfg(unix)]
+                    QueryJsonRpcTransport::Socket(QueryRemoteAddress::Unix(path)) => run_jsonrpc_unix_server(ctx, transport, path).await,
+                }
+            });
+        });
+    }
+}
+
+async fn run_jsonrpc_tcp_server(ctx: TaskContext, transport: QueryRemoteTransport, address: String) {
+    serve_tcp_connections(ctx, "JSON-RPC adapter", address, QueryJsonRpcAddress, move |ctx, stream| {
+        let (reader, writer) = stream.into_split();
+        handle_jsonrpc_connection(ctx, transport, reader, writer)
+    })
+    .await;
+}
+
+#[cfg(unix)]
+async fn run_jsonrpc_unix_server(ctx: TaskContext, transport: QueryRemoteTransport, path: std::path::PathBuf) {
+    serve_unix_connections(ctx, "JSON-RPC adapter", path, move |ctx, stream| {
+        let (reader, writer) = stream.into_split();
+        handle_jsonrpc_connection(ctx, transport, reader, writer)
+    })
+    .await;
+}
+
+async fn handle_jsonrpc_connection<R, W>(ctx: TaskContext, transport: QueryRemoteTransport, reader: R, mut writer: W)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    let connection = uuid::Uuid::new_v4();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<serde_json::Value>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            if let Err(e) = writer.write_all(line.as_bytes()).await.and(writer.flush().await) {
+                warn!("JSON-RPC adapter failed to write a response: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("JSON-RPC adapter failed to read a call: {}", e);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let ctx = ctx.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Some(response) = handle_jsonrpc_message(ctx, transport, connection, &line).await {
+                let _ = sender.send(response);
+            }
+        });
+    }
+
+    drop(sender);
+    let _ = writer_task.await;
+}
+
+/// Serves one JSON-RPC message, a single call or a batch, returning what should be written back
+/// Only the methods registered by `transport` can be called, and ids must be unique among the calls in flight on `connection`
+pub async fn handle_jsonrpc_message(ctx: TaskContext, transport: QueryRemoteTransport, connection: uuid::Uuid, message: &str) -> Option<serde_json::Value> {
+    let message = match serde_json::from_str::<serde_json::Value>(message) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = QueryJsonRpcError::new(QueryJsonRpcError::PARSE_ERROR, format!("Parse error: {}", e));
+            return serde_json::to_value(QueryJsonRpcResponse::failure(serde_json::Value::Null, error)).ok();
+        }
+    };
+
+    let calls = match message {
+        serde_json::Value::Array(calls) if calls.is_empty() => {
+            let error = QueryJsonRpcError::new(QueryJsonRpcError::INVALID_REQUEST, "Empty batch");
+            return serde_json::to_value(QueryJsonRpcResponse::failure(serde_json::Value::Null, error)).ok();
+        }
+        serde_json::Value::Array(calls) => calls,
+        call => {
+            let response = handle_jsonrpc_call(ctx, transport, connection, call).await?;
+            return serde_json::to_value(response).ok();
+        }
+    };
+
+    let handles: Vec<_> = calls.into_iter().map(|call| tokio::spawn(handle_jsonrpc_call(ctx.clone(), transport, connection, call))).collect();
+    let mut responses = Vec::new();
+    for handle in handles {
+        if let Ok(Some(response)) = handle.await {
+            responses.extend(serde_json::to_value(response).ok());
+        }
+    }
+
+    // A batch of notifications has no response at all
+    (!responses.is_empty()).then_some(serde_json::Value::Array(responses))
+}
+
+async fn handle_jsonrpc_call(mut ctx: TaskContext, transport: QueryRemoteTransport, connection: uuid::Uuid, call: serde_json::Value) -> Option<QueryJsonRpcResponse> {
+    let serde_json::Value::Object(mut call) = call else {
+        let error = QueryJsonRpcError::new(QueryJsonRpcError::INVALID_REQUEST, "Call must be an object");
+        return Some(QueryJsonRpcResponse::failure(serde_json::Value::Null, error));
+    };
+
+    let id = call.remove("id");
+    let is_valid_id = id.as_ref().is_none_or(|id| id.is_string() || id.is_number() || id.is_null());
+    let is_valid_version = call.get("jsonrpc").and_then(|version| version.as_str()) == Some("2.0");
+    let method = match call.remove("method") {
+        Some(serde_json::Value::String(method)) if is_valid_id && is_valid_version => method,
+        _ => {
+            let id = id.filter(|_| is_valid_id).unwrap_or_default();
+            let error = QueryJsonRpcError::new(QueryJsonRpcError::INVALID_REQUEST, "Invalid request");
+            return Some(QueryJsonRpcResponse::failure(id, error));
+        }
+    };
+
+    let params = call.remove("params").unwrap_or_default();
+    let uuid = uuid::Uuid::new_v4();
+    match &id {
+        Some(id) => debug!("[{:?}]: JSON-RPC call {} with id {}", uuid, method, id),
+        None => debug!("[{:?}]: JSON-RPC notification {}", uuid, method),
+    }
+
+    let call = QueryJsonRpcCall {
+        connection,
+        id: id.clone().unwrap_or_default(),
+        method: method.clone(),
+    };
+    let is_notification = id.is_none();
+    let accepted = ctx.run_on_main_thread(move |ctx| accept_jsonrpc_call(ctx.world, transport, uuid, call, is_notification)).await;
+    let result = match accepted {
+        Ok(()) => {
+            let request = RemoteQueryRequest {
+                uuid,
+                service: method,
+                request: params,
+            };
+
+            let result = match submit_remote_goal(&mut ctx, transport, request, None).await {
+                Ok(RemoteQueryResponse { reply: Some(reply), .. }) => Ok(reply),
+                Ok(response) => Err(QueryJsonRpcError::new(QueryJsonRpcError::QUERY_FAILED, response.error.unwrap_or_else(|| "Query failed".to_string()))),
+                Err(e) => Err(QueryJsonRpcError::new(QueryJsonRpcError::INVALID_PARAMS, e)),
+            };
+
+            if !is_notification {
+                ctx.run_on_main_thread(move |ctx| {
+                    if let Some(mut calls) = ctx.world.get_resource_mut::<QueryJsonRpcCalls>() {
+                        calls.calls.remove(&uuid);
+                    }
+                })
+                .await;
+            }
+            result
+        }
+        Err(error) => Err(error),
+    };
+
+    let id = id?;
+    Some(match result {
+        Ok(reply) => QueryJsonRpcResponse::success(id, reply),
+        Err(error) => QueryJsonRpcResponse::failure(id, error),
+    })
+}
+
+/// Checks the method of a call and records it under the uuid of its goal, unless it is a notification
+/// Calls reusing an id still in flight on their connection are refused
+fn accept_jsonrpc_call(world: &mut World, transport: QueryRemoteTransport, uuid: uuid::Uuid, call: QueryJsonRpcCall, is_notification: bool) -> Result<(), QueryJsonRpcError> {
+    if !contains_remote_service(world, transport, &call.method) {
+        return Err(QueryJsonRpcError::new(QueryJsonRpcError::METHOD_NOT_FOUND, format!("Method not found: {}", call.method)));
+    }
+
+    if is_notification {
+        return Ok(());
+    }
+
+    let mut calls = world.get_resource_or_insert_with(QueryJsonRpcCalls::default);
+    if calls.get_uuid(call.connection, &call.id).is_some() {
+        return Err(QueryJsonRpcError::new(QueryJsonRpcError::INVALID_REQUEST, format!("Call {} is already in flight", call.id)));
+    }
+
+    calls.calls.insert(uuid, call);
+    Ok(())
+}
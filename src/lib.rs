@@ -18,14 +18,44 @@
 // =========================================================================
 use bevy_app::prelude::*;
 use bevy_ecs::prelude::*;
+use bevy_hierarchy::prelude::*;
 use bevy_log::prelude::*;
+use bevy_time::prelude::*;
+#[cfg(feature = "brp")]
+mod brp;
+#[cfg(feature = "remote")]
+mod codecs;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "jsonrpc")]
+mod jsonrpc;
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "schema")]
+mod schema;
 mod structs;
 mod systems;
 mod traits;
+#[cfg(feature = "websocket")]
+mod websocket;
 
+#[cfg(feature = "brp")]
+pub use brp::*;
+#[cfg(feature = "remote")]
+pub use codecs::*;
+#[cfg(feature = "http")]
+pub use http::*;
+#[cfg(feature = "jsonrpc")]
+pub use jsonrpc::*;
+#[cfg(feature = "remote")]
+pub use remote::*;
+#[cfg(feature = "schema")]
+pub use schema::*;
 pub use structs::*;
 pub use systems::*;
 pub use traits::*;
+#[cfg(feature = "websocket")]
+pub use websocket::*;
 
 use anyhow::Result;
 
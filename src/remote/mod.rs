@@ -0,0 +1,1191 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use super::*;
+use bevy_ecs::world::DeferredWorld;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Frames larger than this are refused
+pub const MAX_FRAME_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// A request sent by a remote client, `request` is the serialized request content of `service`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteQueryRequest {
+    pub uuid: uuid::Uuid,
+    pub service: String,
+    pub request: serde_json::Value,
+}
+
+/// The answer to a `RemoteQueryRequest`, carrying either the serialized reply content or an error
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteQueryResponse {
+    pub uuid: uuid::Uuid,
+    pub reply: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+impl RemoteQueryResponse {
+    pub fn success(uuid: uuid::Uuid, reply: serde_json::Value) -> Self {
+        Self {
+            uuid,
+            reply: Some(reply),
+            error: None,
+        }
+    }
+
+    pub fn failure(uuid: uuid::Uuid, error: impl Into<String>) -> Self {
+        Self {
+            uuid,
+            reply: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// The first frame a client may send, in JSON, to switch its connection to another codec
+/// The server answers with the codec it picked, or with no codec if it supports none of them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteQueryHandshake {
+    pub codecs: Vec<String>,
+}
+
+/// A request on a connection that negotiated a codec, `request` being encoded with the same codec
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteEncodedRequest {
+    pub uuid: uuid::Uuid,
+    pub service: String,
+    #[serde(with = "serde_bytes")]
+    pub request: Vec<u8>,
+}
+
+/// The answer to a `RemoteEncodedRequest`, `reply` being encoded with the codec of the connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteEncodedResponse {
+    pub uuid: uuid::Uuid,
+    #[serde(with = "serde_bytes")]
+    pub reply: Option<Vec<u8>>,
+    pub error: Option<String>,
+}
+
+impl RemoteEncodedResponse {
+    pub fn success(uuid: uuid::Uuid, reply: Vec<u8>) -> Self {
+        Self {
+            uuid,
+            reply: Some(reply),
+            error: None,
+        }
+    }
+
+    pub fn failure(uuid: uuid::Uuid, error: impl Into<String>) -> Self {
+        Self {
+            uuid,
+            reply: None,
+            error: Some(error.into()),
+        }
+    }
+}
+
+/// Reads one length-prefixed frame, `None` when the stream is closed
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let length = match reader.read_u32().await {
+        Ok(length) => length,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if length > MAX_FRAME_LENGTH {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Frame of {} bytes is too large", length)));
+    }
+
+    let mut frame = vec![0; length as usize];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+/// Writes one frame prefixed with its length as a big-endian `u32`
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> std::io::Result<()> {
+    let length = u32::try_from(frame.len()).ok().filter(|length| *length <= MAX_FRAME_LENGTH);
+    let Some(length) = length else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Frame of {} bytes is too large", frame.len())));
+    };
+
+    writer.write_u32(length).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+type RemoteSpawner = fn(&mut World, uuid::Uuid, serde_json::Value) -> Result<Entity>;
+type RemoteReplier = fn(&World, Entity) -> Option<Result<serde_json::Value, String>>;
+type RemoteFeedbackReader = fn(&World, Entity, usize) -> Vec<serde_json::Value>;
+#[cfg(feature = "schema")]
+type RemoteSchemaWriter = fn(&mut schemars::SchemaGenerator) -> (schemars::Schema, schemars::Schema);
+type RemoteEncodedSpawner = fn(&mut World, uuid::Uuid, &[u8]) -> Result<Entity>;
+type RemoteEncodedReplier = fn(&World, Entity) -> Option<Result<Vec<u8>, String>>;
+
+/// Encodes the frames of a connection that negotiated a codec
+#[derive(Clone, Copy)]
+struct RemoteFrameCodec {
+    decode_request: fn(&[u8]) -> Result<RemoteEncodedRequest>,
+    encode_response: fn(&RemoteEncodedResponse) -> Result<Vec<u8>>,
+}
+
+/// Serves a service on connections that negotiated a codec, decoding its requests and encoding its replies with that codec
+#[derive(Clone, Copy)]
+struct RemoteEncodedService {
+    spawn: RemoteEncodedSpawner,
+    reply: RemoteEncodedReplier,
+}
+
+/// Request and reply contents of remote services
+/// With the `schema` feature they also need a `JsonSchema`, so that `get_query_schema` describes every remote service
+#[cfg(feature = "schema")]
+pub trait QueryRemoteContent: schemars::JsonSchema {}
+
+#[cfg(feature = "schema")]
+impl<T: schemars::JsonSchema> QueryRemoteContent for T {}
+
+/// Request and reply contents of remote services
+/// With the `schema` feature they also need a `JsonSchema`, so that `get_query_schema` describes every remote service
+#[cfg(not(feature = "schema"))]
+pub trait QueryRemoteContent {}
+
+#[cfg(not(feature = "schema"))]
+impl<T> QueryRemoteContent for T {}
+
+#[derive(Clone, Copy)]
+struct RemoteService {
+    spawn: RemoteSpawner,
+    reply: RemoteReplier,
+    feedback: Option<RemoteFeedbackReader>,
+    #[cfg(feature = "schema")]
+    schema: RemoteSchemaWriter,
+}
+
+/// Query services reachable by one remote transport, by name
+#[derive(Default)]
+pub struct QueryRemoteServices {
+    services: HashMap<String, RemoteService>,
+    codecs: HashMap<&'static str, RemoteFrameCodec>,
+    encoded: HashMap<(String, &'static str), RemoteEncodedService>,
+}
+
+impl QueryRemoteServices {
+    /// Exposes the service serving `T` with `U` under `name`
+    /// The service itself still needs its server or client system
+    pub fn register<T, U>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+    {
+        self.services.insert(
+            name.into(),
+            RemoteService {
+                spawn: spawn_remote_request::<T, U>,
+                reply: get_remote_reply::<U>,
+                feedback: None,
+                #[cfg(feature = "schema")]
+                schema: get_remote_schemas::<T, U>,
+            },
+        );
+        self
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`
+    /// Its `QueryFeedback<F>` items are streamed by the transports that support feedback
+    pub fn register_with_feedback<T, U, F>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+        F: Serialize + Send + Sync + 'static,
+    {
+        self.services.insert(
+            name.into(),
+            RemoteService {
+                spawn: spawn_remote_request::<T, U>,
+                reply: get_remote_reply::<U>,
+                feedback: Some(get_remote_feedbacks::<F>),
+                #[cfg(feature = "schema")]
+                schema: get_remote_schemas::<T, U>,
+            },
+        );
+        self
+    }
+
+    /// Also accepts the service `name` on connections that negotiated the codec `C`
+    /// Every service accepts JSON, and the service itself still needs to be registered
+    pub fn register_codec<T, U, C>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+        U: Serialize + Send + Sync + 'static,
+        C: QueryCodec,
+    {
+        self.codecs.insert(
+            C::NAME,
+            RemoteFrameCodec {
+                decode_request: C::decode::<RemoteEncodedRequest>,
+                encode_response: C::encode::<RemoteEncodedResponse>,
+            },
+        );
+        self.encoded.insert(
+            (name.into(), C::NAME),
+            RemoteEncodedService {
+                spawn: spawn_encoded_request::<T, U, C>,
+                reply: get_encoded_reply::<U, C>,
+            },
+        );
+        self
+    }
+
+    /// The codecs accepted by at least one service, besides JSON
+    pub fn get_codec_names(&self) -> impl Iterator<Item = &str> {
+        self.codecs.keys().copied()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.services.contains_key(name)
+    }
+
+    pub fn get_names(&self) -> impl Iterator<Item = &str> {
+        self.services.keys().map(|name| name.as_str())
+    }
+
+    /// The services with the writers of their request and reply schemas
+    #[cfg(feature = "schema")]
+    pub(crate) fn get_schemas(&self) -> impl Iterator<Item = (&str, RemoteSchemaWriter)> {
+        self.services.iter().map(|(name, service)| (name.as_str(), service.schema))
+    }
+}
+
+/// Identifies the service table of one remote transport plugin
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QueryRemoteTransport(usize);
+
+/// The service tables of the remote transport plugins
+/// Each plugin only serves the services it registered itself
+#[derive(Resource, Default)]
+pub struct QueryRemoteTransports {
+    tables: Vec<QueryRemoteServices>,
+}
+
+impl QueryRemoteTransports {
+    pub fn get(&self, transport: QueryRemoteTransport) -> Option<&QueryRemoteServices> {
+        self.tables.get(transport.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &QueryRemoteServices> {
+        self.tables.iter()
+    }
+}
+
+/// Looks up the service `name` in the table of `transport` only
+#[cfg(any(feature = "http", feature = "jsonrpc"))]
+pub(crate) fn contains_remote_service(world: &World, transport: QueryRemoteTransport, name: &str) -> bool {
+    world
+        .get_resource::<QueryRemoteTransports>()
+        .and_then(|transports| transports.get(transport))
+        .is_some_and(|services| services.contains(name))
+}
+
+struct RemotePendingQuery {
+    entity: Entity,
+    reply: RemotePendingReply,
+    feedback: Option<RemotePendingFeedback>,
+}
+
+/// Where the outcome of a pending query goes, in the format of the connection that sent it
+enum RemotePendingReply {
+    Json(RemoteReplier, tokio::sync::oneshot::Sender<RemoteQueryResponse>),
+    Encoded(RemoteEncodedReplier, tokio::sync::oneshot::Sender<RemoteEncodedResponse>),
+}
+
+impl RemotePendingReply {
+    /// Sends the outcome of the goal of `entity`, or gives the reply back if the goal has not finished
+    fn send(self, world: &World, uuid: uuid::Uuid, entity: Entity) -> Result<(), Self> {
+        match self {
+            Self::Json(reply, sender) => {
+                let Some(outcome) = reply(world, entity) else {
+                    return Err(Self::Json(reply, sender));
+                };
+                let _ = sender.send(match outcome {
+                    Ok(reply) => RemoteQueryResponse::success(uuid, reply),
+                    Err(e) => RemoteQueryResponse::failure(uuid, e),
+                });
+            }
+            Self::Encoded(reply, sender) => {
+                let Some(outcome) = reply(world, entity) else {
+                    return Err(Self::Encoded(reply, sender));
+                };
+                let _ = sender.send(match outcome {
+                    Ok(reply) => RemoteEncodedResponse::success(uuid, reply),
+                    Err(e) => RemoteEncodedResponse::failure(uuid, e),
+                });
+            }
+        }
+        debug!("[{:?}]: Sending remote reply", uuid);
+        Ok(())
+    }
+
+    fn fail(self, uuid: uuid::Uuid, error: &str) {
+        match self {
+            Self::Json(_, sender) => {
+                let _ = sender.send(RemoteQueryResponse::failure(uuid, error));
+            }
+            Self::Encoded(_, sender) => {
+                let _ = sender.send(RemoteEncodedResponse::failure(uuid, error));
+            }
+        }
+    }
+}
+
+struct RemotePendingFeedback {
+    read: RemoteFeedbackReader,
+    sent: usize,
+    sender: tokio::sync::mpsc::UnboundedSender<serde_json::Value>,
+}
+
+impl RemotePendingQuery {
+    /// Forwards the feedback items produced since the last call
+    fn send_feedbacks(&mut self, world: &World) {
+        let Some(feedback) = self.feedback.as_mut() else {
+            return;
+        };
+
+        for item in (feedback.read)(world, self.entity, feedback.sent) {
+            feedback.sent += 1;
+            let _ = feedback.sender.send(item);
+        }
+    }
+}
+
+/// Remote queries waiting for their goal to finish, by goal uuid
+#[derive(Resource, Default)]
+pub struct QueryRemotePending {
+    queries: HashMap<uuid::Uuid, RemotePendingQuery>,
+}
+
+impl QueryRemotePending {
+    pub fn len(&self) -> usize {
+        self.queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queries.is_empty()
+    }
+}
+
+/// Where a remote query server listens or a remote query client connects
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QueryRemoteAddress {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl From<&str> for QueryRemoteAddress {
+    fn from(address: &str) -> Self {
+        Self::Tcp(address.to_string())
+    }
+}
+
+impl From<String> for QueryRemoteAddress {
+    fn from(address: String) -> Self {
+        Self::Tcp(address)
+    }
+}
+
+impl From<std::net::SocketAddr> for QueryRemoteAddress {
+    fn from(address: std::net::SocketAddr) -> Self {
+        Self::Tcp(address.to_string())
+    }
+}
+
+impl std::fmt::Display for QueryRemoteAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "{}", address),
+            #[cfg(unix)]
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// The address the remote query server is listening on, inserted once the socket is bound
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QueryRemoteServerAddress(pub std::net::SocketAddr);
+
+fn spawn_remote_request<T, U>(world: &mut World, uuid: uuid::Uuid, request: serde_json::Value) -> Result<Entity>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let request = serde_json::from_value::<T>(request)?;
+    spawn_request::<T, U>(world, QueryEvent::new(uuid, request).with_reply::<U>()).ok_or_else(|| anyhow::anyhow!("Request was not accepted"))
+}
+
+fn get_remote_reply<U>(world: &World, entity: Entity) -> Option<Result<serde_json::Value, String>>
+where
+    U: Serialize + Send + Sync + 'static,
+{
+    get_goal_outcome::<U>(world, entity).map(|outcome| outcome.and_then(|reply| serde_json::to_value(reply).map_err(|e| e.to_string())))
+}
+
+fn spawn_encoded_request<T, U, C>(world: &mut World, uuid: uuid::Uuid, request: &[u8]) -> Result<Entity>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+    U: Send + Sync + 'static,
+    C: QueryCodec,
+{
+    let request = C::decode::<T>(request).map_err(|e| anyhow::anyhow!("Invalid request: {}", e))?;
+    spawn_request::<T, U>(world, QueryEvent::new(uuid, request).with_reply::<U>()).ok_or_else(|| anyhow::anyhow!("Request was not accepted"))
+}
+
+fn get_encoded_reply<U, C>(world: &World, entity: Entity) -> Option<Result<Vec<u8>, String>>
+where
+    U: Serialize + Send + Sync + 'static,
+    C: QueryCodec,
+{
+    get_goal_outcome::<U>(world, entity).map(|outcome| outcome.and_then(|reply| C::encode(reply).map_err(|e| format!("Failed to encode the reply: {}", e))))
+}
+
+#[cfg(feature = "schema")]
+fn get_remote_schemas<T, U>(generator: &mut schemars::SchemaGenerator) -> (schemars::Schema, schemars::Schema)
+where
+    T: schemars::JsonSchema,
+    U: schemars::JsonSchema,
+{
+    (generator.subschema_for::<T>(), generator.subschema_for::<U>())
+}
+
+fn get_remote_feedbacks<F>(world: &World, entity: Entity, skip: usize) -> Vec<serde_json::Value>
+where
+    F: Serialize + Send + Sync + 'static,
+{
+    let Some(feedback) = world.get::<QueryFeedback<F>>(entity) else {
+        return Vec::new();
+    };
+
+    feedback
+        .feedbacks
+        .iter()
+        .skip(skip)
+        .filter_map(|item| serde_json::to_value(item).inspect_err(|e| warn!("Failed to encode a remote feedback: {}", e)).ok())
+        .collect()
+}
+
+/// Spawns the goal of a remote request on the main thread and waits for it to finish
+/// Shared by every remote transport, which only reaches the services in its own table
+pub async fn submit_remote_query(ctx: &mut TaskContext, transport: QueryRemoteTransport, request: RemoteQueryRequest) -> RemoteQueryResponse {
+    let uuid = request.uuid;
+    submit_remote_goal(ctx, transport, request, None).await.unwrap_or_else(|e| RemoteQueryResponse::failure(uuid, e))
+}
+
+/// Like `submit_remote_query`, also forwarding the feedback of services registered with `register_with_feedback` to `feedback`
+/// Every feedback item is sent before the response is returned
+pub async fn submit_remote_query_with_feedback(
+    ctx: &mut TaskContext,
+    transport: QueryRemoteTransport,
+    request: RemoteQueryRequest,
+    feedback: tokio::sync::mpsc::UnboundedSender<serde_json::Value>,
+) -> RemoteQueryResponse {
+    let uuid = request.uuid;
+    submit_remote_goal(ctx, transport, request, Some(feedback))
+        .await
+        .unwrap_or_else(|e| RemoteQueryResponse::failure(uuid, e))
+}
+
+/// Fails without a response when the request is refused before its goal is spawned
+pub(crate) async fn submit_remote_goal(
+    ctx: &mut TaskContext,
+    transport: QueryRemoteTransport,
+    request: RemoteQueryRequest,
+    feedback: Option<tokio::sync::mpsc::UnboundedSender<serde_json::Value>>,
+) -> Result<RemoteQueryResponse, String> {
+    let uuid = request.uuid;
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let submitted = ctx
+        .run_on_main_thread(move |ctx| {
+            let service = ctx
+                .world
+                .get_resource::<QueryRemoteTransports>()
+                .and_then(|transports| transports.get(transport))
+                .and_then(|services| services.services.get(&request.service).copied());
+            let Some(service) = service else {
+                return Err(format!("Unknown service: {}", request.service));
+            };
+
+            let feedback = service.feedback.zip(feedback).map(|(read, sender)| RemotePendingFeedback { read, sent: 0, sender });
+            let reply = RemotePendingReply::Json(service.reply, sender);
+            start_remote_query(ctx.world, uuid, |world| (service.spawn)(world, uuid, request.request), reply, feedback)
+        })
+        .await;
+
+    if let Err(e) = submitted {
+        warn!("[{:?}]: Remote request refused: {}", uuid, e);
+        return Err(e);
+    }
+
+    Ok(receiver.await.unwrap_or_else(|_| RemoteQueryResponse::failure(uuid, "Query service stopped before replying")))
+}
+
+/// Spawns the goal of a remote query with `spawn`, keeping the query pending until `reply` is sent
+/// Requests reusing the uuid of a remote query still in flight are refused, so each pending query keeps its sender
+fn start_remote_query(world: &mut World, uuid: uuid::Uuid, spawn: impl FnOnce(&mut World) -> Result<Entity>, reply: RemotePendingReply, feedback: Option<RemotePendingFeedback>) -> Result<(), String> {
+    if world.get_resource::<QueryRemotePending>().is_some_and(|pending| pending.queries.contains_key(&uuid)) {
+        return Err(format!("Request {} is already in flight", uuid));
+    }
+
+    let entity = spawn(world).map_err(|e| e.to_string())?;
+    world
+        .get_resource_or_insert_with(QueryRemotePending::default)
+        .queries
+        .insert(uuid, RemotePendingQuery { entity, reply, feedback });
+    Ok(())
+}
+
+/// Sends the outcome of finished goals back to the remote transports waiting for them
+pub fn reply_remote_queries(world: &mut World) {
+    let Some(mut pending) = world.remove_resource::<QueryRemotePending>() else {
+        return;
+    };
+
+    for (uuid, mut query) in std::mem::take(&mut pending.queries) {
+        query.send_feedbacks(world);
+        if let Err(reply) = query.reply.send(world, uuid, query.entity) {
+            query.reply = reply;
+            pending.queries.insert(uuid, query);
+        }
+    }
+
+    world.insert_resource(pending);
+}
+
+/// Answers a remote query whose goal is despawned before `reply_remote_queries` saw it finish
+/// The goal components are still readable while it is being removed
+pub fn reply_removed_remote_query(trigger: Trigger<OnRemove, GoalComponent>, mut world: DeferredWorld) {
+    let entity = trigger.entity();
+    let Some(uuid) = world.get::<GoalComponent>(entity).map(|goal| goal.get_uuid()) else {
+        return;
+    };
+
+    let Some(mut pending) = world.get_resource_mut::<QueryRemotePending>() else {
+        return;
+    };
+
+    if pending.queries.get(&uuid).is_none_or(|query| query.entity != entity) {
+        return;
+    }
+
+    let Some(mut query) = pending.queries.remove(&uuid) else {
+        return;
+    };
+
+    query.send_feedbacks(&world);
+    if let Err(reply) = query.reply.send(&world, uuid, entity) {
+        reply.fail(uuid, "Request was despawned before completion");
+    }
+}
+
+/// Cancels the goal of a pending remote query, which then answers with an error
+/// Returns `false` if no remote query with this uuid is pending
+pub fn cancel_remote_query(world: &mut World, uuid: uuid::Uuid) -> bool {
+    let Some(entity) = world.get_resource::<QueryRemotePending>().and_then(|pending| pending.queries.get(&uuid)).map(|query| query.entity) else {
+        return false;
+    };
+
+    let Some(mut goal) = world.get_mut::<GoalComponent>(entity) else {
+        return false;
+    };
+
+    if goal.is_completed() || goal.is_cancelled() {
+        return false;
+    }
+
+    goal.mark_cancelled();
+    info!("[{:?}]: Goal is cancelled by its remote client", uuid);
+    true
+}
+
+pub(crate) type RemoteRegistration = Box<dyn Fn(&mut QueryRemoteServices) + Send + Sync>;
+
+/// Exposes query services to other processes over TCP or a Unix socket
+/// Each frame is a JSON `RemoteQueryRequest` or `RemoteQueryResponse` prefixed with its length
+/// Requires the `TokioTasksPlugin`
+pub struct QueryRemoteServerPlugin {
+    address: QueryRemoteAddress,
+    registrations: Vec<RemoteRegistration>,
+}
+
+impl QueryRemoteServerPlugin {
+    pub fn new(address: impl Into<QueryRemoteAddress>) -> Self {
+        Self {
+            address: address.into(),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Listens on the Unix socket at `path` instead
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<std::path::PathBuf>) -> Self {
+        Self::new(QueryRemoteAddress::Unix(path.into()))
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`
+    pub fn with_service<T, U>(mut self, name: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register::<T, U>(name.clone());
+        }));
+        self
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`, also accepting the codec `C`
+    /// Can be called again with other codecs for the same service
+    pub fn with_service_codec<T, U, C>(mut self, name: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+        C: QueryCodec,
+    {
+        let name = name.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register::<T, U>(name.clone());
+            services.register_codec::<T, U, C>(name.clone());
+        }));
+        self
+    }
+}
+
+impl Plugin for QueryRemoteServerPlugin {
+    fn build(&self, app: &mut App) {
+        let transport = add_remote_services(app, &self.registrations);
+
+        let address = self.address.clone();
+        app.add_systems(Startup, move |runtime: Res<TokioTasksRuntime>| {
+            let address = address.clone();
+            runtime.spawn_background_task(move |ctx| run_remote_server(ctx, transport, address));
+        });
+    }
+}
+
+/// Registers the services of a remote transport plugin in a table of its own
+/// The systems replying to remote queries are only added by the first transport
+pub(crate) fn add_remote_services(app: &mut App, registrations: &[RemoteRegistration]) -> QueryRemoteTransport {
+    if !app.world().contains_resource::<QueryRemotePending>() {
+        app.init_resource::<QueryRemotePending>();
+        app.add_systems(Update, reply_remote_queries.before(cleanup_requests));
+        app.add_observer(reply_removed_remote_query);
+    }
+
+    let mut services = QueryRemoteServices::default();
+    for registration in registrations {
+        registration(&mut services);
+    }
+
+    let mut transports = app.world_mut().get_resource_or_insert_with(QueryRemoteTransports::default);
+    transports.tables.push(services);
+    QueryRemoteTransport(transports.tables.len() - 1)
+}
+
+async fn run_remote_server(ctx: TaskContext, transport: QueryRemoteTransport, address: QueryRemoteAddress) {
+    match address {
+        QueryRemoteAddress::Tcp(address) => run_tcp_server(ctx, transport, address).await,
+        #[cfg(unix)]
+        QueryRemoteAddress::Unix(path) => run_unix_server(ctx, transport, path).await,
+    }
+}
+
+async fn run_tcp_server(ctx: TaskContext, transport: QueryRemoteTransport, address: String) {
+    serve_tcp_connections(ctx, "Query server", address, QueryRemoteServerAddress, move |ctx, stream| {
+        handle_remote_connection(ctx, transport, stream)
+    })
+    .await;
+}
+
+#[cfg(unix)]
+async fn run_unix_server(ctx: TaskContext, transport: QueryRemoteTransport, path: std::path::PathBuf) {
+    serve_unix_connections(ctx, "Query server", path, move |ctx, stream| handle_remote_connection(ctx, transport, stream)).await;
+}
+
+/// Listens on `address` as `name`, inserting the bound address as a resource, and hands every accepted connection to `handle`
+pub(crate) async fn serve_tcp_connections<R, H, F>(mut ctx: TaskContext, name: &'static str, address: String, to_resource: fn(std::net::SocketAddr) -> R, handle: H)
+where
+    R: Resource,
+    H: Fn(TaskContext, tokio::net::TcpStream) -> F,
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let listener = match tokio::net::TcpListener::bind(&address).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("{} failed to listen on {}: {}", name, address, e);
+            return;
+        }
+    };
+
+    if let Ok(local_address) = listener.local_addr() {
+        info!("{} listening on {}", name, local_address);
+        ctx.run_on_main_thread(move |ctx| ctx.world.insert_resource(to_resource(local_address))).await;
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("{} accepted a connection from {}", name, peer);
+                tokio::spawn(handle(ctx.clone(), stream));
+            }
+            Err(e) => warn!("{} failed to accept a connection: {}", name, e),
+        }
+    }
+}
+
+/// Listens on the Unix socket at `path` as `name`, and hands every accepted connection to `handle`
+#[cfg(unix)]
+pub(crate) async fn serve_unix_connections<H, F>(ctx: TaskContext, name: &'static str, path: std::path::PathBuf, handle: H)
+where
+    H: Fn(TaskContext, tokio::net::UnixStream) -> F,
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let listener = match tokio::net::UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("{} failed to listen on {}: {}", name, path.display(), e);
+            return;
+        }
+    };
+    info!("{} listening on {}", name, path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                debug!("{} accepted a connection on {}", name, path.display());
+                tokio::spawn(handle(ctx.clone(), stream));
+            }
+            Err(e) => warn!("{} failed to accept a connection: {}", name, e),
+        }
+    }
+}
+
+async fn handle_remote_connection<S>(ctx: TaskContext, transport: QueryRemoteTransport, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(frame) = receiver.recv().await {
+            if let Err(e) = write_frame(&mut writer, &frame).await {
+                warn!("Query server failed to write a reply: {}", e);
+                break;
+            }
+        }
+    });
+
+    let mut codec = None;
+    let mut is_first_frame = true;
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Query server failed to read a request: {}", e);
+                break;
+            }
+        };
+
+        if std::mem::take(&mut is_first_frame) {
+            if let Ok(handshake) = serde_json::from_slice::<RemoteQueryHandshake>(&frame) {
+                let mut ctx = ctx.clone();
+                let (name, frame_codec) = negotiate_codec(&mut ctx, transport, handshake).await;
+                codec = name.zip(frame_codec);
+                let reply = RemoteQueryHandshake {
+                    codecs: name.map(|name| name.to_string()).into_iter().collect(),
+                };
+                let _ = sender.send(serde_json::to_vec(&reply).unwrap_or_default());
+                continue;
+            }
+        }
+
+        let mut ctx = ctx.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Some((name, frame_codec)) = codec {
+                if let Some(frame) = serve_encoded_request(&mut ctx, transport, name, frame_codec, &frame).await {
+                    let _ = sender.send(frame);
+                }
+                return;
+            }
+
+            let response = match serde_json::from_slice::<RemoteQueryRequest>(&frame) {
+                Ok(request) => submit_remote_query(&mut ctx, transport, request).await,
+                Err(e) => RemoteQueryResponse::failure(uuid::Uuid::nil(), format!("Invalid request frame: {}", e)),
+            };
+
+            match serde_json::to_vec(&response) {
+                Ok(frame) => {
+                    let _ = sender.send(frame);
+                }
+                Err(e) => error!("[{:?}]: Failed to encode the remote reply: {}", response.uuid, e),
+            }
+        });
+    }
+
+    drop(sender);
+    let _ = writer_task.await;
+}
+
+/// Picks the first codec of the handshake the server supports
+/// JSON keeps the plain `RemoteQueryRequest` frames, so it has no frame codec
+async fn negotiate_codec(ctx: &mut TaskContext, transport: QueryRemoteTransport, handshake: RemoteQueryHandshake) -> (Option<&'static str>, Option<RemoteFrameCodec>) {
+    ctx.run_on_main_thread(move |ctx| {
+        let services = ctx.world.get_resource::<QueryRemoteTransports>().and_then(|transports| transports.get(transport));
+        for name in &handshake.codecs {
+            if name == QueryJsonCodec::NAME {
+                return (Some(QueryJsonCodec::NAME), None);
+            }
+
+            if let Some((name, frame_codec)) = services.and_then(|services| services.codecs.get_key_value(name.as_str())) {
+                return (Some(*name), Some(*frame_codec));
+            }
+        }
+        (None, None)
+    })
+    .await
+}
+
+async fn serve_encoded_request(ctx: &mut TaskContext, transport: QueryRemoteTransport, codec: &'static str, frame_codec: RemoteFrameCodec, frame: &[u8]) -> Option<Vec<u8>> {
+    let response = match (frame_codec.decode_request)(frame) {
+        Ok(request) => serve_encoded_query(ctx, transport, codec, request).await,
+        Err(e) => RemoteEncodedResponse::failure(uuid::Uuid::nil(), format!("Invalid request frame: {}", e)),
+    };
+
+    (frame_codec.encode_response)(&response)
+        .inspect_err(|e| error!("[{:?}]: Failed to encode the remote reply: {}", response.uuid, e))
+        .ok()
+}
+
+async fn serve_encoded_query(ctx: &mut TaskContext, transport: QueryRemoteTransport, codec: &'static str, request: RemoteEncodedRequest) -> RemoteEncodedResponse {
+    let uuid = request.uuid;
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    let submitted = ctx
+        .run_on_main_thread(move |ctx| {
+            let services = ctx.world.get_resource::<QueryRemoteTransports>().and_then(|transports| transports.get(transport));
+            let Some(services) = services.filter(|services| services.contains(&request.service)) else {
+                return Err(format!("Unknown service: {}", request.service));
+            };
+
+            let Some(service) = services.encoded.get(&(request.service.clone(), codec)).copied() else {
+                return Err(format!("Service {} does not accept the {} codec", request.service, codec));
+            };
+
+            let reply = RemotePendingReply::Encoded(service.reply, sender);
+            start_remote_query(ctx.world, uuid, |world| (service.spawn)(world, uuid, &request.request), reply, None)
+        })
+        .await;
+
+    if let Err(e) = submitted {
+        warn!("[{:?}]: Remote request refused: {}", uuid, e);
+        return RemoteEncodedResponse::failure(uuid, e);
+    }
+
+    receiver.await.unwrap_or_else(|_| RemoteEncodedResponse::failure(uuid, "Query service stopped before replying"))
+}
+
+trait RemoteStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> RemoteStream for S {}
+
+/// Idle connections by codec
+type RemoteConnectionPools = HashMap<&'static str, Vec<Box<dyn RemoteStream>>>;
+
+/// Sends requests to a remote query server, keeping idle connections around for reuse
+/// Connections are pooled per codec, and clones share the same pools
+#[derive(Resource, Clone)]
+pub struct QueryRemoteClient {
+    address: QueryRemoteAddress,
+    max_idle: usize,
+    idle: std::sync::Arc<std::sync::Mutex<RemoteConnectionPools>>,
+}
+
+impl QueryRemoteClient {
+    pub fn new(address: impl Into<QueryRemoteAddress>) -> Self {
+        Self {
+            address: address.into(),
+            max_idle: 8,
+            idle: Default::default(),
+        }
+    }
+
+    /// Connects to the Unix socket at `path` instead
+    #[cfg(unix)]
+    pub fn unix(path: impl Into<std::path::PathBuf>) -> Self {
+        Self::new(QueryRemoteAddress::Unix(path.into()))
+    }
+
+    /// Connections beyond `max_idle` per codec are closed once their request is answered
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    pub fn get_address(&self) -> &QueryRemoteAddress {
+        &self.address
+    }
+
+    pub fn get_max_idle(&self) -> usize {
+        self.max_idle
+    }
+
+    pub fn get_idle_count(&self) -> usize {
+        self.idle.lock().map(|idle| idle.values().map(|streams| streams.len()).sum()).unwrap_or(0)
+    }
+
+    /// Sends `request` to the service `service` of the remote server and decodes its reply
+    pub async fn query<T, U>(&self, service: impl Into<String>, request: &T) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        self.query_with::<QueryJsonCodec, T, U>(service, request).await
+    }
+
+    /// Like `query`, over a connection that negotiated the codec `C`
+    pub async fn query_with<C, T, U>(&self, service: impl Into<String>, request: &T) -> Result<U>
+    where
+        C: QueryCodec,
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let request = C::encode(request)?;
+        self.query_encoded::<C, U>(service.into(), request).await
+    }
+
+    async fn query_encoded<C, U>(&self, service: String, request: Vec<u8>) -> Result<U>
+    where
+        C: QueryCodec,
+        U: DeserializeOwned,
+    {
+        let uuid = uuid::Uuid::new_v4();
+        if C::NAME == QueryJsonCodec::NAME {
+            let request = RemoteQueryRequest {
+                uuid,
+                service,
+                request: serde_json::from_slice(&request)?,
+            };
+            let response = self.send(&request).await?;
+            return Ok(serde_json::from_value(get_remote_response_reply(uuid, response.reply, response.error)?)?);
+        }
+
+        let frame = C::encode(&RemoteEncodedRequest { uuid, service, request })?;
+        let response = self
+            .round_trip(C::NAME, uuid, &frame, |frame| {
+                let response = C::decode::<RemoteEncodedResponse>(frame)?;
+                Ok((response.uuid, response))
+            })
+            .await?;
+        C::decode(&get_remote_response_reply(uuid, response.reply, response.error)?)
+    }
+
+    /// Sends `request` as is and waits for its response
+    pub async fn send(&self, request: &RemoteQueryRequest) -> Result<RemoteQueryResponse> {
+        let frame = serde_json::to_vec(request)?;
+        self.round_trip(QueryJsonCodec::NAME, request.uuid, &frame, |frame| {
+            let response = serde_json::from_slice::<RemoteQueryResponse>(frame)?;
+            Ok((response.uuid, response))
+        })
+        .await
+    }
+
+    /// A pooled connection failing before the request is written is replaced by a new one
+    /// Once written, the request may have reached the server, so it is not sent again under the same uuid
+    async fn round_trip<R>(&self, codec: &'static str, uuid: uuid::Uuid, frame: &[u8], decode: impl Fn(&[u8]) -> Result<(uuid::Uuid, R)>) -> Result<R> {
+        let mut stream = match self.take_idle(codec) {
+            Some(mut stream) => match write_frame(&mut stream, frame).await {
+                Ok(()) => stream,
+                Err(e) => {
+                    debug!("[{:?}]: Pooled connection to {} failed, reconnecting: {}", uuid, self.address, e);
+                    self.connect_and_write(codec, frame).await?
+                }
+            },
+            None => self.connect_and_write(codec, frame).await?,
+        };
+
+        let response = read_frame(&mut stream).await?.ok_or_else(|| anyhow::anyhow!("Connection closed by the query server"))?;
+        self.finish_exchange(codec, stream, uuid, decode(&response))
+    }
+
+    async fn connect_and_write(&self, codec: &'static str, frame: &[u8]) -> Result<Box<dyn RemoteStream>> {
+        let mut stream = self.connect(codec).await?;
+        write_frame(&mut stream, frame).await?;
+        Ok(stream)
+    }
+
+    async fn connect(&self, codec: &'static str) -> Result<Box<dyn RemoteStream>> {
+        debug!("Connecting to the query server on {}", self.address);
+        let mut stream: Box<dyn RemoteStream> = match &self.address {
+            QueryRemoteAddress::Tcp(address) => {
+                let stream = tokio::net::TcpStream::connect(address).await?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            #[cfg(unix)]
+            QueryRemoteAddress::Unix(path) => Box::new(tokio::net::UnixStream::connect(path).await?),
+        };
+
+        if codec != QueryJsonCodec::NAME {
+            let handshake = RemoteQueryHandshake { codecs: vec![codec.to_string()] };
+            let reply = exchange_frame(&mut stream, &serde_json::to_vec(&handshake)?).await?;
+            let reply = serde_json::from_slice::<RemoteQueryHandshake>(&reply)?;
+            if reply.codecs.first().map(|name| name.as_str()) != Some(codec) {
+                return Err(anyhow::anyhow!("Query server on {} does not support the {} codec", self.address, codec));
+            }
+        }
+        Ok(stream)
+    }
+
+    /// Pops a pooled connection of `codec`, dropping those the server closed while they were idle
+    fn take_idle(&self, codec: &'static str) -> Option<Box<dyn RemoteStream>> {
+        let mut idle = self.idle.lock().ok()?;
+        let streams = idle.get_mut(codec)?;
+        while let Some(mut stream) = streams.pop() {
+            if is_idle_stream_open(&mut stream) {
+                return Some(stream);
+            }
+            debug!("Dropping a pooled connection closed by the query server on {}", self.address);
+        }
+        None
+    }
+
+    fn finish_exchange<R>(&self, codec: &'static str, stream: Box<dyn RemoteStream>, uuid: uuid::Uuid, response: Result<(uuid::Uuid, R)>) -> Result<R> {
+        // A connection answering garbage or another uuid is out of step, so it is not pooled again
+        let (response_uuid, response) = response?;
+        if response_uuid != uuid {
+            return Err(anyhow::anyhow!("[{:?}]: Remote response is for {:?}", uuid, response_uuid));
+        }
+
+        if let Ok(mut idle) = self.idle.lock() {
+            let streams = idle.entry(codec).or_default();
+            if streams.len() < self.max_idle {
+                streams.push(stream);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// An idle connection has nothing to read until it is used again
+/// A read that does not wait means the server closed it, or sent something out of step
+fn is_idle_stream_open(stream: &mut Box<dyn RemoteStream>) -> bool {
+    let mut byte = [0; 1];
+    let mut buffer = tokio::io::ReadBuf::new(&mut byte);
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    std::pin::Pin::new(stream).poll_read(&mut context, &mut buffer).is_pending()
+}
+
+async fn exchange_frame(stream: &mut Box<dyn RemoteStream>, frame: &[u8]) -> Result<Vec<u8>> {
+    write_frame(stream, frame).await?;
+    read_frame(stream).await?.ok_or_else(|| anyhow::anyhow!("Connection closed by the query server"))
+}
+
+fn get_remote_response_reply<R>(uuid: uuid::Uuid, reply: Option<R>, error: Option<String>) -> Result<R> {
+    if let Some(e) = error {
+        return Err(anyhow::anyhow!(e));
+    }
+
+    reply.ok_or_else(|| anyhow::anyhow!("[{:?}]: Remote response has no reply", uuid))
+}
+
+/// Implement on a reply type to have its client fetch it from the server of the `QueryRemoteClient` resource
+/// `run_query_client` then forwards each request to the remote service named by `get_service_name`, encoded with `Codec`
+pub trait QueryRemoteClientOps<T> {
+    type Codec: QueryCodec;
+
+    fn get_service_name() -> &'static str;
+}
+
+impl<T, U> QueryClientOps<T> for U
+where
+    T: Serialize + Send + Sync + 'static,
+    U: QueryRemoteClientOps<T> + DeserializeOwned + Send + 'static,
+{
+    fn send_request(ctx: &mut TaskContext, request: &QueryRequest<T>) -> impl std::future::Future<Output = Result<Self>> + Send {
+        let request = U::Codec::encode(&request.request);
+        let mut ctx = ctx.clone();
+        async move {
+            let request = request?;
+            let client = ctx
+                .run_on_main_thread(|ctx| ctx.world.get_resource::<QueryRemoteClient>().cloned())
+                .await
+                .ok_or_else(|| anyhow::anyhow!("QueryRemoteClient resource is missing"))?;
+            client.query_encoded::<U::Codec, U>(U::get_service_name().to_string(), request).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"first").await.unwrap();
+        write_frame(&mut buffer, b"").await.unwrap();
+        assert_eq!(&buffer[..4], &5u32.to_be_bytes());
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(b"first".to_vec()));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), Some(Vec::new()));
+        assert_eq!(read_frame(&mut reader).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn frames_over_the_maximum_length_are_refused() {
+        let mut reader = &(MAX_FRAME_LENGTH + 1).to_be_bytes()[..];
+        let error = read_frame(&mut reader).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+        let mut buffer = Vec::new();
+        let error = write_frame(&mut buffer, &vec![0; MAX_FRAME_LENGTH as usize + 1]).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_frames_are_errors() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"truncated").await.unwrap();
+        buffer.pop();
+
+        let error = read_frame(&mut buffer.as_slice()).await.unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,152 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use serde_json::{json, Map, Value};
+
+/// Dialect of the exported schema
+pub const QUERY_SCHEMA_DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Request of the built-in schema query, served by `run_query_server::<QuerySchemaRequest, QuerySchema>`
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct QuerySchemaRequest;
+
+/// Reply of the built-in schema query, the document of `get_query_schema`
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct QuerySchema {
+    pub document: Value,
+}
+
+impl QueryServerOps<QuerySchemaRequest> for QuerySchema {
+    fn get_reply(world: &mut World, _: &QueryRequest<QuerySchemaRequest>) -> Result<Self> {
+        Ok(Self { document: get_query_schema(world) })
+    }
+}
+
+/// Builds a JSON Schema document for the services registered by the remote transport plugins
+/// Each service lists the schemas of its request and reply under `services`, with named types under `$defs`
+/// The schemas follow the serde layout of the types, as sent by every remote transport
+/// A name served by several transports is described once
+pub fn get_query_schema(world: &World) -> Value {
+    let mut services = Map::new();
+    let mut generator = schemars::SchemaGenerator::default();
+
+    for table in world.get_resource::<QueryRemoteTransports>().into_iter().flat_map(|transports| transports.iter()) {
+        for (name, write) in table.get_schemas() {
+            if services.contains_key(name) {
+                continue;
+            }
+
+            let (request, reply) = write(&mut generator);
+            services.insert(name.to_string(), json!({ "request": request, "reply": reply }));
+        }
+    }
+
+    json!({
+        "$schema": QUERY_SCHEMA_DIALECT,
+        "services": services,
+        "$defs": generator.take_definitions(true),
+    })
+}
+
+/// Writes the document of `get_query_schema` as pretty JSON to `path`
+pub fn write_query_schema(world: &World, path: impl AsRef<std::path::Path>) -> Result<()> {
+    let document = serde_json::to_string_pretty(&get_query_schema(world))?;
+    std::fs::write(path, document)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, schemars::JsonSchema)]
+    struct Position {
+        x: f32,
+        #[serde(rename = "height")]
+        y: f32,
+    }
+
+    #[derive(Serialize, Deserialize, schemars::JsonSchema)]
+    enum Command {
+        Stop,
+        Move { speed: f32 },
+    }
+
+    #[derive(Serialize, Deserialize, schemars::JsonSchema)]
+    struct Lookup {
+        name: Option<String>,
+    }
+
+    /// Registers `move` on one transport and `lookup` on another
+    fn get_document() -> Value {
+        let mut app = App::new();
+        let registrations: Vec<RemoteRegistration> = vec![Box::new(|services| {
+            services.register::<Position, Command>("move");
+        })];
+        add_remote_services(&mut app, &registrations);
+        let registrations: Vec<RemoteRegistration> = vec![Box::new(|services| {
+            services.register::<Lookup, std::collections::HashMap<String, u32>>("lookup");
+        })];
+        add_remote_services(&mut app, &registrations);
+        get_query_schema(app.world())
+    }
+
+    #[test]
+    fn describes_the_services_of_every_transport() {
+        let document = get_document();
+        assert_eq!(document["$schema"], QUERY_SCHEMA_DIALECT);
+        assert_eq!(
+            document["services"]["move"],
+            json!({ "request": { "$ref": "#/$defs/Position" }, "reply": { "$ref": "#/$defs/Command" } })
+        );
+        assert_eq!(document["services"]["lookup"]["request"], json!({ "$ref": "#/$defs/Lookup" }));
+    }
+
+    #[test]
+    fn structs_follow_their_serde_names() {
+        let position = &get_document()["$defs"]["Position"];
+        assert_eq!(position["type"], "object");
+        assert_eq!(position["properties"]["height"]["type"], "number");
+        assert!(position["properties"].get("y").is_none());
+        assert_eq!(position["required"], json!(["x", "height"]));
+    }
+
+    #[test]
+    fn enums_are_externally_tagged() {
+        let variants = &get_document()["$defs"]["Command"]["oneOf"];
+        assert_eq!(variants[0], json!({ "type": "string", "enum": ["Stop"] }));
+        assert_eq!(variants[1]["properties"]["Move"]["properties"]["speed"]["type"], "number");
+        assert_eq!(variants[1]["required"], json!(["Move"]));
+    }
+
+    #[test]
+    fn options_accept_null() {
+        let lookup = &get_document()["$defs"]["Lookup"];
+        assert_eq!(lookup["properties"]["name"]["type"], json!(["string", "null"]));
+        assert!(lookup.get("required").is_none());
+    }
+
+    #[test]
+    fn maps_are_objects_of_their_values() {
+        let reply = &get_document()["services"]["lookup"]["reply"];
+        assert_eq!(reply["type"], "object");
+        assert_eq!(reply["additionalProperties"]["type"], "integer");
+        assert_eq!(reply["additionalProperties"]["minimum"], 0);
+    }
+}
@@ -22,6 +22,104 @@ use super::*;
 pub struct QueryEvent<T> {
     pub uuid: uuid::Uuid,
     pub request: T,
+    reply_type: Option<std::any::TypeId>,
+}
+
+impl<T> QueryEvent<T> {
+    pub fn new(uuid: uuid::Uuid, request: T) -> Self {
+        Self { uuid, request, reply_type: None }
+    }
+
+    /// Only the server or client replying with `U` will handle this request
+    pub fn with_reply<U: 'static>(mut self) -> Self {
+        self.reply_type = Some(std::any::TypeId::of::<U>());
+        self
+    }
+
+    /// The reply content wanted by the sender, any reply if `None`
+    pub fn get_reply_type(&self) -> Option<std::any::TypeId> {
+        self.reply_type
+    }
+
+    /// Whether an endpoint for reply content `U` should accept this request
+    pub fn accepts_reply<U: 'static>(&self) -> bool {
+        self.reply_type.is_none_or(|reply_type| reply_type == std::any::TypeId::of::<U>())
+    }
+
+    /// Binds a request without reply content to `U`, so that the other endpoints of `T` skip it
+    /// Returns whether an endpoint for reply content `U` should accept this request
+    /// When `T` has endpoints for several reply contents, a request without reply content is refused for every endpoint
+    pub(crate) fn claim_reply<U: 'static>(&mut self, endpoints: Option<&QueryEndpoints<T>>) -> bool
+    where
+        T: 'static,
+    {
+        if self.reply_type.is_none() && endpoints.is_some_and(|endpoints| endpoints.len() > 1) {
+            warn!(
+                "[{:?}]: Request refused, its request content has endpoints for several reply contents and it does not pick one with `with_reply`",
+                self.uuid
+            );
+            // No endpoint replies with `QueryEndpoints<T>`, so the other endpoints skip it without warning again
+            self.reply_type = Some(std::any::TypeId::of::<QueryEndpoints<T>>());
+            return false;
+        }
+
+        *self.reply_type.get_or_insert(std::any::TypeId::of::<U>()) == std::any::TypeId::of::<U>()
+    }
+}
+
+/// The reply contents of the endpoints of `T`, recorded when the endpoint systems are initialized
+#[derive(Resource)]
+pub struct QueryEndpoints<T> {
+    reply_types: Vec<std::any::TypeId>,
+    _marker: std::marker::PhantomData<fn() -> T>,
+}
+
+impl<T> Default for QueryEndpoints<T> {
+    fn default() -> Self {
+        Self {
+            reply_types: Vec::new(),
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<T> QueryEndpoints<T> {
+    pub fn register<U: 'static>(&mut self) {
+        let reply_type = std::any::TypeId::of::<U>();
+        if !self.reply_types.contains(&reply_type) {
+            self.reply_types.push(reply_type);
+        }
+    }
+
+    pub fn contains<U: 'static>(&self) -> bool {
+        self.reply_types.contains(&std::any::TypeId::of::<U>())
+    }
+
+    /// The number of distinct reply contents
+    pub fn len(&self) -> usize {
+        self.reply_types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reply_types.is_empty()
+    }
+}
+
+/// Records `U` in the `QueryEndpoints<T>` resource when the endpoint system holding it as a `Local` is initialized
+/// Every endpoint is then known before the first one reads a request
+pub struct QueryEndpointRegistration<T, U> {
+    _marker: std::marker::PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> FromWorld for QueryEndpointRegistration<T, U>
+where
+    T: Send + Sync + 'static,
+    U: 'static,
+{
+    fn from_world(world: &mut World) -> Self {
+        world.get_resource_or_insert_with(QueryEndpoints::<T>::default).register::<U>();
+        Self { _marker: std::marker::PhantomData }
+    }
 }
 
 #[derive(Component, Debug, Clone)]
@@ -44,7 +142,14 @@ pub struct GoalComponent {
     uuid: uuid::Uuid,
     is_executing: bool,
     is_completed: bool,
+    is_rejected: bool,
+    is_failed: bool,
+    is_fallback: bool,
+    is_cancelled: bool,
     to_delete: bool,
+    reason: Option<String>,
+    winning_attempt: Option<u32>,
+    reply_type: Option<std::any::TypeId>,
     timer: bevy_time::Stopwatch,
 }
 
@@ -54,7 +159,14 @@ impl GoalComponent {
             uuid,
             is_executing: false,
             is_completed: false,
+            is_rejected: false,
+            is_failed: false,
+            is_fallback: false,
+            is_cancelled: false,
             to_delete: false,
+            reason: None,
+            winning_attempt: None,
+            reply_type: None,
             timer: bevy_time::Stopwatch::new(),
         }
     }
@@ -63,6 +175,20 @@ impl GoalComponent {
         self.uuid
     }
 
+    /// Binds the goal to the reply content `U`, other servers and clients of the same request will skip it
+    pub fn with_reply<U: 'static>(mut self) -> Self {
+        self.reply_type = Some(std::any::TypeId::of::<U>());
+        self
+    }
+
+    pub fn get_reply_type(&self) -> Option<std::any::TypeId> {
+        self.reply_type
+    }
+
+    pub fn accepts_reply<U: 'static>(&self) -> bool {
+        self.reply_type.is_none_or(|reply_type| reply_type == std::any::TypeId::of::<U>())
+    }
+
     pub fn mark_executing(&mut self) {
         self.is_executing = true;
     }
@@ -80,6 +206,64 @@ impl GoalComponent {
         self.is_completed
     }
 
+    /// Rejected goals are also marked for deletion
+    pub fn mark_rejected(&mut self, reason: impl Into<String>) {
+        self.is_executing = false;
+        self.is_rejected = true;
+        self.to_delete = true;
+        self.reason = Some(reason.into());
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        self.is_rejected
+    }
+
+    /// Failed goals are also marked for deletion
+    pub fn mark_failed(&mut self, reason: impl Into<String>) {
+        self.is_executing = false;
+        self.is_failed = true;
+        self.to_delete = true;
+        self.reason = Some(reason.into());
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.is_failed
+    }
+
+    /// Records that the primary service failed with `reason` and the reply comes from the fallback
+    pub fn mark_fallback(&mut self, reason: impl Into<String>) {
+        self.is_fallback = true;
+        self.reason = Some(reason.into());
+    }
+
+    pub fn is_fallback(&self) -> bool {
+        self.is_fallback
+    }
+
+    /// Records which attempt of a hedged request produced the reply, starting from 1
+    pub fn mark_winning_attempt(&mut self, attempt: u32) {
+        self.winning_attempt = Some(attempt);
+    }
+
+    pub fn get_winning_attempt(&self) -> Option<u32> {
+        self.winning_attempt
+    }
+
+    pub fn get_reason(&self) -> Option<&str> {
+        self.reason.as_deref()
+    }
+
+    /// Cancelled goals are also marked for deletion, along with their sub-requests
+    pub fn mark_cancelled(&mut self) {
+        self.is_executing = false;
+        self.is_cancelled = true;
+        self.to_delete = true;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.is_cancelled
+    }
+
     pub fn mark_to_delete(&mut self) {
         self.is_executing = false;
         self.to_delete = true;
@@ -89,3 +273,1398 @@ impl GoalComponent {
         self.to_delete
     }
 }
+
+/// Sub-requests spawned by an aggregate goal with `spawn_child_request`, in spawn order
+/// The sub-requests are also children of the goal entity
+#[derive(Component, Debug, Clone, Default)]
+pub struct QuerySubRequests {
+    entities: Vec<Entity>,
+}
+
+impl QuerySubRequests {
+    pub fn push(&mut self, entity: Entity) {
+        self.entities.push(entity);
+    }
+
+    pub fn get_entities(&self) -> &[Entity] {
+        &self.entities
+    }
+}
+
+type QueryChainStep = Box<dyn Fn(&mut World, Entity, Option<Entity>) -> Result<Entity> + Send + Sync>;
+type QueryChainFinish = Box<dyn Fn(&mut World, Entity, Entity) -> Result<()> + Send + Sync>;
+
+/// Builder for a chain of requests where each request is built from the reply of the previous one
+/// Every step is spawned as a sub-request of one overall goal and served by the usual server or client systems
+/// `U` is the reply content of the last step
+pub struct QueryChain<U> {
+    uuid: uuid::Uuid,
+    steps: Vec<QueryChainStep>,
+    _marker: std::marker::PhantomData<fn() -> U>,
+}
+
+impl<U> QueryChain<U>
+where
+    U: Clone + Send + Sync + 'static,
+{
+    /// Starts a chain with the first request, the uuid of the event becomes the uuid of the overall goal
+    pub fn new<T>(event: QueryEvent<T>) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        let request = event.request;
+        Self {
+            uuid: event.uuid,
+            steps: vec![Box::new(move |world, parent, _| Ok(spawn_child_request::<T, U>(world, parent, request.clone())))],
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Adds a step whose request is built from the previous reply
+    pub fn then<T, V>(self, map: impl Fn(&QueryReply<U>) -> T + Send + Sync + 'static) -> QueryChain<V>
+    where
+        T: Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        self.and_then(move |reply| Ok(map(reply)))
+    }
+
+    /// Adds a step whose request is built from the previous reply, an error fails the overall goal
+    pub fn and_then<T, V>(mut self, map: impl Fn(&QueryReply<U>) -> Result<T> + Send + Sync + 'static) -> QueryChain<V>
+    where
+        T: Send + Sync + 'static,
+        V: Clone + Send + Sync + 'static,
+    {
+        self.steps.push(Box::new(move |world, parent, previous| {
+            let previous = previous.ok_or_else(|| anyhow::anyhow!("Chain step has no previous request"))?;
+            let reply = world.get::<QueryReply<U>>(previous).ok_or_else(|| anyhow::anyhow!("Reply of the previous step is missing"))?;
+            let request = map(reply)?;
+            Ok(spawn_child_request::<T, V>(world, parent, request))
+        }));
+
+        QueryChain {
+            uuid: self.uuid,
+            steps: self.steps,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn get_uuid(&self) -> uuid::Uuid {
+        self.uuid
+    }
+
+    /// Spawns the overall goal, which is driven by `run_query_chains`
+    pub fn spawn(self, commands: &mut Commands) -> Entity {
+        let finish: QueryChainFinish = Box::new(|world, parent, last| {
+            let reply = world.get::<QueryReply<U>>(last).cloned().ok_or_else(|| anyhow::anyhow!("Reply of the last step is missing"))?;
+            world.entity_mut(parent).insert(reply);
+            Ok(())
+        });
+
+        info!("[{:?}]: Chain spawned with {} steps", self.uuid, self.steps.len());
+        commands
+            .spawn((
+                GoalComponent::new(self.uuid).with_reply::<U>(),
+                QueryChainState {
+                    steps: std::sync::Arc::new(self.steps),
+                    finish: std::sync::Arc::new(finish),
+                    next_step: 0,
+                },
+            ))
+            .id()
+    }
+}
+
+/// Progress of a chain spawned by `QueryChain`
+#[derive(Component, Clone)]
+pub struct QueryChainState {
+    steps: std::sync::Arc<Vec<QueryChainStep>>,
+    finish: std::sync::Arc<QueryChainFinish>,
+    next_step: usize,
+}
+
+impl QueryChainState {
+    /// Number of steps started so far
+    pub fn get_step(&self) -> usize {
+        self.next_step
+    }
+
+    pub fn get_len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub(crate) fn run_step(&self, world: &mut World, parent: Entity, previous: Option<Entity>) -> Result<Entity> {
+        (self.steps[self.next_step])(world, parent, previous)
+    }
+
+    pub(crate) fn finish(&self, world: &mut World, parent: Entity, last: Entity) -> Result<()> {
+        (self.finish)(world, parent, last)
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.next_step += 1;
+    }
+}
+
+type QueryJoinRequest = Box<dyn FnOnce(&mut World, Entity) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryJoinMode {
+    /// Completes when every request has completed, fails as soon as one fails
+    All,
+    /// Completes when the first request completes and cancels the others, fails if every request fails
+    Race,
+}
+
+/// Builder for one overall goal that fans out to several requests, possibly to different services
+/// The requests are spawned as sub-requests of the overall goal and their replies stay on the sub-request entities
+pub struct QueryJoin {
+    uuid: uuid::Uuid,
+    mode: QueryJoinMode,
+    requests: Vec<QueryJoinRequest>,
+}
+
+impl QueryJoin {
+    pub fn all(uuid: uuid::Uuid) -> Self {
+        Self {
+            uuid,
+            mode: QueryJoinMode::All,
+            requests: Vec::new(),
+        }
+    }
+
+    pub fn race(uuid: uuid::Uuid) -> Self {
+        Self {
+            uuid,
+            mode: QueryJoinMode::Race,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Adds a request for the service serving `T` with `U`
+    pub fn with<T, U>(mut self, request: T) -> Self
+    where
+        T: Send + Sync + 'static,
+        U: Send + Sync + 'static,
+    {
+        self.requests.push(Box::new(move |world, parent| {
+            spawn_child_request::<T, U>(world, parent, request);
+        }));
+        self
+    }
+
+    pub fn get_uuid(&self) -> uuid::Uuid {
+        self.uuid
+    }
+
+    /// Spawns the overall goal, which is driven by `run_query_joins`
+    pub fn spawn(self, commands: &mut Commands) -> Entity {
+        info!("[{:?}]: Join spawned with {} requests", self.uuid, self.requests.len());
+        let parent = commands
+            .spawn((GoalComponent::new(self.uuid), QuerySubRequests::default(), QueryJoinState { mode: self.mode, winner: None }))
+            .id();
+        let requests = self.requests;
+        commands.queue(move |world: &mut World| {
+            for request in requests {
+                request(world, parent);
+            }
+        });
+        parent
+    }
+}
+
+/// State of a goal spawned by `QueryJoin`
+#[derive(Component, Debug, Clone)]
+pub struct QueryJoinState {
+    mode: QueryJoinMode,
+    winner: Option<Entity>,
+}
+
+impl QueryJoinState {
+    pub fn get_mode(&self) -> QueryJoinMode {
+        self.mode
+    }
+
+    /// The sub-request that won a race
+    pub fn get_winner(&self) -> Option<Entity> {
+        self.winner
+    }
+
+    pub(crate) fn mark_winner(&mut self, winner: Entity) {
+        self.winner = Some(winner);
+    }
+}
+
+type QuerySagaRequest = Box<dyn Fn(&mut World, Entity) -> Entity + Send + Sync>;
+
+struct QuerySagaStep {
+    request: QuerySagaRequest,
+    compensation: Option<QuerySagaRequest>,
+}
+
+/// Builder for a multi-step operation where each step can declare a compensating request
+/// When a step fails, the compensations of the completed steps run in reverse order
+pub struct QuerySaga {
+    uuid: uuid::Uuid,
+    steps: Vec<QuerySagaStep>,
+}
+
+impl QuerySaga {
+    pub fn new(uuid: uuid::Uuid) -> Self {
+        Self { uuid, steps: Vec::new() }
+    }
+
+    /// Adds a step served by the service serving `T` with `U`
+    pub fn step<T, U>(mut self, request: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        U: Send + Sync + 'static,
+    {
+        self.steps.push(QuerySagaStep {
+            request: Box::new(move |world, parent| spawn_child_request::<T, U>(world, parent, request.clone())),
+            compensation: None,
+        });
+        self
+    }
+
+    /// Sets the compensating request of the last step, served by the service serving `T` with `U`
+    pub fn compensate_with<T, U>(mut self, request: T) -> Self
+    where
+        T: Clone + Send + Sync + 'static,
+        U: Send + Sync + 'static,
+    {
+        match self.steps.last_mut() {
+            Some(step) => step.compensation = Some(Box::new(move |world, parent| spawn_child_request::<T, U>(world, parent, request.clone()))),
+            None => warn!("[{:?}]: Compensation added before any saga step, ignoring it", self.uuid),
+        }
+        self
+    }
+
+    pub fn get_uuid(&self) -> uuid::Uuid {
+        self.uuid
+    }
+
+    /// Spawns the overall goal, which is driven by `run_query_sagas`
+    pub fn spawn(self, commands: &mut Commands) -> Entity {
+        info!("[{:?}]: Saga spawned with {} steps", self.uuid, self.steps.len());
+        commands
+            .spawn((
+                GoalComponent::new(self.uuid),
+                QuerySagaState {
+                    steps: std::sync::Arc::new(self.steps),
+                    phase: QuerySagaPhase::Running,
+                    completed_steps: 0,
+                    compensation_cursor: 0,
+                    compensated_steps: 0,
+                    failed_compensations: 0,
+                    current: None,
+                    failure: None,
+                },
+            ))
+            .id()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuerySagaPhase {
+    Running,
+    Compensating,
+    Completed,
+    Compensated,
+}
+
+/// Progress of a saga spawned by `QuerySaga`
+#[derive(Component, Clone)]
+pub struct QuerySagaState {
+    steps: std::sync::Arc<Vec<QuerySagaStep>>,
+    phase: QuerySagaPhase,
+    completed_steps: usize,
+    compensation_cursor: usize,
+    compensated_steps: usize,
+    failed_compensations: usize,
+    current: Option<Entity>,
+    failure: Option<String>,
+}
+
+impl QuerySagaState {
+    pub fn get_phase(&self) -> QuerySagaPhase {
+        self.phase
+    }
+
+    pub fn get_len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn get_completed_steps(&self) -> usize {
+        self.completed_steps
+    }
+
+    pub fn get_compensated_steps(&self) -> usize {
+        self.compensated_steps
+    }
+
+    pub fn get_failed_compensations(&self) -> usize {
+        self.failed_compensations
+    }
+
+    /// The reason of the step failure that triggered compensation
+    pub fn get_failure(&self) -> Option<&str> {
+        self.failure.as_deref()
+    }
+
+    /// The sub-request currently running, either a step or a compensation
+    pub fn get_current(&self) -> Option<Entity> {
+        self.current
+    }
+
+    /// Spawns the next step, or moves to `Completed` once every step has completed
+    pub(crate) fn start_next_step(&mut self, world: &mut World, parent: Entity) {
+        match self.steps.get(self.completed_steps) {
+            Some(step) => self.current = Some((step.request)(world, parent)),
+            None => {
+                self.current = None;
+                self.phase = QuerySagaPhase::Completed;
+            }
+        }
+    }
+
+    pub(crate) fn complete_step(&mut self) {
+        self.completed_steps += 1;
+    }
+
+    pub(crate) fn start_compensation(&mut self, failure: String) {
+        self.phase = QuerySagaPhase::Compensating;
+        self.compensation_cursor = self.completed_steps;
+        self.failure = Some(failure);
+    }
+
+    /// Spawns the compensation of the latest completed step that has one, or moves to `Compensated`
+    pub(crate) fn start_next_compensation(&mut self, world: &mut World, parent: Entity) {
+        while self.compensation_cursor > 0 {
+            self.compensation_cursor -= 1;
+            if let Some(compensation) = &self.steps[self.compensation_cursor].compensation {
+                self.current = Some(compensation(world, parent));
+                return;
+            }
+        }
+        self.current = None;
+        self.phase = QuerySagaPhase::Compensated;
+    }
+
+    pub(crate) fn complete_compensation(&mut self, is_success: bool) {
+        match is_success {
+            true => self.compensated_steps += 1,
+            false => self.failed_compensations += 1,
+        }
+    }
+}
+
+type QueryUndo = Box<dyn FnOnce(&mut World)>;
+
+/// Records the changes a server handler makes to the world so they can be reverted
+/// Only changes made through the transaction are recorded, reads go through `world()`
+pub struct QueryTransaction<'w> {
+    world: &'w mut World,
+    undo: Vec<QueryUndo>,
+}
+
+impl<'w> QueryTransaction<'w> {
+    pub fn new(world: &'w mut World) -> Self {
+        Self { world, undo: Vec::new() }
+    }
+
+    pub fn world(&self) -> &World {
+        self.world
+    }
+
+    /// Builds a query state, to be iterated with `world()`
+    pub fn query<D: bevy_ecs::query::QueryData>(&mut self) -> QueryState<D> {
+        self.world.query::<D>()
+    }
+
+    pub fn query_filtered<D: bevy_ecs::query::QueryData, F: bevy_ecs::query::QueryFilter>(&mut self) -> QueryState<D, F> {
+        self.world.query_filtered::<D, F>()
+    }
+
+    pub fn get<C: Component>(&self, entity: Entity) -> Option<&C> {
+        self.world.get::<C>(entity)
+    }
+
+    pub fn get_mut<C: Component + Clone>(&mut self, entity: Entity) -> Option<Mut<'_, C>> {
+        let previous = self.world.get::<C>(entity)?.clone();
+        self.record_component(entity, Some(previous));
+        self.world.get_mut::<C>(entity)
+    }
+
+    /// Fails if `entity` does not exist, e.g. a stale entity taken from the request
+    pub fn insert<C: Component + Clone>(&mut self, entity: Entity, component: C) -> Result<()> {
+        let Ok(mut entity_mut) = self.world.get_entity_mut(entity) else {
+            return Err(anyhow::anyhow!("Entity {:?} does not exist", entity));
+        };
+
+        let previous = entity_mut.get::<C>().cloned();
+        entity_mut.insert(component);
+        self.record_component(entity, previous);
+        Ok(())
+    }
+
+    /// Returns `None` if `entity` does not exist or does not have `C`
+    pub fn remove<C: Component + Clone>(&mut self, entity: Entity) -> Option<C> {
+        let previous = self.world.get_entity_mut(entity).ok()?.take::<C>()?;
+        self.record_component(entity, Some(previous.clone()));
+        Some(previous)
+    }
+
+    /// Entities spawned through the transaction are despawned on rollback
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.world.spawn(bundle).id();
+        self.undo.push(Box::new(move |world| {
+            world.despawn(entity);
+        }));
+        entity
+    }
+
+    pub fn resource<R: Resource>(&self) -> Option<&R> {
+        self.world.get_resource::<R>()
+    }
+
+    pub fn resource_mut<R: Resource + Clone>(&mut self) -> Option<Mut<'_, R>> {
+        let previous = self.world.get_resource::<R>()?.clone();
+        self.undo.push(Box::new(move |world| {
+            world.insert_resource(previous);
+        }));
+        self.world.get_resource_mut::<R>()
+    }
+
+    pub fn commit(self) {
+        debug!("Transaction committed with {} changes", self.undo.len());
+    }
+
+    /// Reverts the recorded changes, most recent first
+    pub fn rollback(self) {
+        debug!("Transaction rolled back with {} changes", self.undo.len());
+        for undo in self.undo.into_iter().rev() {
+            undo(self.world);
+        }
+    }
+
+    fn record_component<C: Component>(&mut self, entity: Entity, previous: Option<C>) {
+        self.undo.push(Box::new(move |world| {
+            let Ok(mut entity) = world.get_entity_mut(entity) else {
+                return;
+            };
+            match previous {
+                Some(previous) => {
+                    entity.insert(previous);
+                }
+                None => {
+                    entity.remove::<C>();
+                }
+            }
+        }));
+    }
+}
+
+enum QueryRouteMatcher<T> {
+    Predicate(fn(&T) -> bool),
+    Key(String),
+}
+
+struct QueryRoute<T, U> {
+    matcher: QueryRouteMatcher<T>,
+    handler: QueryHandler<T, U>,
+}
+
+/// Dispatches requests of `T` to one of several handlers, served by `run_query_router`
+/// Routes are tried in registration order, so handlers can be contributed by different plugins
+#[derive(Resource)]
+pub struct QueryRouter<T, U> {
+    key: Option<fn(&T) -> String>,
+    routes: Vec<QueryRoute<T, U>>,
+}
+
+impl<T, U> Default for QueryRouter<T, U> {
+    fn default() -> Self {
+        Self { key: None, routes: Vec::new() }
+    }
+}
+
+impl<T, U> QueryRouter<T, U> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how the route key of a request is computed, used by keyed routes
+    pub fn set_key(&mut self, key: fn(&T) -> String) -> &mut Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn add_route(&mut self, predicate: fn(&T) -> bool, handler: QueryHandler<T, U>) -> &mut Self {
+        self.routes.push(QueryRoute {
+            matcher: QueryRouteMatcher::Predicate(predicate),
+            handler,
+        });
+        self
+    }
+
+    pub fn add_keyed_route(&mut self, key: impl Into<String>, handler: QueryHandler<T, U>) -> &mut Self {
+        self.routes.push(QueryRoute {
+            matcher: QueryRouteMatcher::Key(key.into()),
+            handler,
+        });
+        self
+    }
+
+    pub fn get_route_count(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Returns the handler of the first matching route
+    pub fn resolve(&self, request: &T) -> Option<QueryHandler<T, U>> {
+        let key = self.key.map(|key| key(request));
+        self.routes
+            .iter()
+            .find(|route| match &route.matcher {
+                QueryRouteMatcher::Predicate(predicate) => predicate(request),
+                QueryRouteMatcher::Key(route_key) => key.as_ref() == Some(route_key),
+            })
+            .map(|route| route.handler)
+    }
+}
+
+/// One-shot systems run by `run_query_callbacks` when a goal spawned with `commands.query` finishes
+#[derive(Component)]
+pub struct QueryCallbacks<U: Send + Sync + 'static> {
+    on_reply: Option<bevy_ecs::system::SystemId<In<QueryReply<U>>>>,
+    on_error: Option<bevy_ecs::system::SystemId<In<String>>>,
+}
+
+impl<U: Send + Sync + 'static> Default for QueryCallbacks<U> {
+    fn default() -> Self {
+        Self { on_reply: None, on_error: None }
+    }
+}
+
+impl<U: Send + Sync + 'static> Clone for QueryCallbacks<U> {
+    fn clone(&self) -> Self {
+        Self {
+            on_reply: self.on_reply,
+            on_error: self.on_error,
+        }
+    }
+}
+
+impl<U: Send + Sync + 'static> QueryCallbacks<U> {
+    pub fn get_on_reply(&self) -> Option<bevy_ecs::system::SystemId<In<QueryReply<U>>>> {
+        self.on_reply
+    }
+
+    pub fn get_on_error(&self) -> Option<bevy_ecs::system::SystemId<In<String>>> {
+        self.on_error
+    }
+}
+
+/// Returned by `commands.query` to attach callbacks to the spawned goal
+pub struct QueryEntityCommands<'a, U: Send + Sync + 'static> {
+    entity: EntityCommands<'a>,
+    callbacks: QueryCallbacks<U>,
+}
+
+impl<'a, U: Clone + Send + Sync + 'static> QueryEntityCommands<'a, U> {
+    pub fn new(entity: EntityCommands<'a>) -> Self {
+        Self {
+            entity,
+            callbacks: QueryCallbacks::default(),
+        }
+    }
+
+    pub fn id(&self) -> Entity {
+        self.entity.id()
+    }
+
+    /// Runs `callback` once with the reply when the goal completes
+    pub fn on_reply(mut self, callback: impl Fn(&QueryReply<U>, &mut Commands) + Send + Sync + 'static) -> Self {
+        let system = self
+            .entity
+            .commands()
+            .register_system(move |In(reply): In<QueryReply<U>>, mut commands: Commands| callback(&reply, &mut commands));
+        self.observe_callbacks();
+        if let Some(previous) = self.callbacks.on_reply.replace(system) {
+            self.entity.commands().unregister_system(previous);
+        }
+        self.entity.insert(self.callbacks.clone());
+        self
+    }
+
+    /// Runs `callback` once with the reason when the goal fails, is rejected or is cancelled
+    pub fn on_error(mut self, callback: impl Fn(&str, &mut Commands) + Send + Sync + 'static) -> Self {
+        let system = self
+            .entity
+            .commands()
+            .register_system(move |In(reason): In<String>, mut commands: Commands| callback(&reason, &mut commands));
+        self.observe_callbacks();
+        if let Some(previous) = self.callbacks.on_error.replace(system) {
+            self.entity.commands().unregister_system(previous);
+        }
+        self.entity.insert(self.callbacks.clone());
+        self
+    }
+
+    /// Before the first callback, observes the goal so that callbacks still registered when it goes away are unregistered
+    fn observe_callbacks(&mut self) {
+        if self.callbacks.on_reply.is_none() && self.callbacks.on_error.is_none() {
+            self.entity.observe(unregister_query_callbacks::<U>);
+        }
+    }
+}
+
+/// Optional intake check for requests of type `T`
+/// Requests that fail validation are spawned as rejected goals and never reach a server or client
+#[derive(Resource)]
+pub struct QueryValidator<T> {
+    validate: fn(&T) -> Result<()>,
+}
+
+impl<T> QueryValidator<T> {
+    pub fn new(validate: fn(&T) -> Result<()>) -> Self {
+        Self { validate }
+    }
+
+    pub fn validate(&self, request: &T) -> Result<()> {
+        (self.validate)(request)
+    }
+}
+
+/// What happens to a goal that finds the rate limiter empty
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueryRateLimitPolicy {
+    /// The goal stays pending and is retried on the next run
+    #[default]
+    Wait,
+    /// The goal is marked as rejected
+    Reject,
+}
+
+/// Token bucket rate limiter for the query service serving `T` with `U`
+/// Tokens are refilled from `bevy_time::Time`, so the limiter follows virtual time
+/// Without a `Time` resource the limiter never refills, which is warned about once
+#[derive(Resource)]
+pub struct QueryRateLimiter<T, U> {
+    capacity: f64,
+    refill_per_second: f64,
+    tokens: f64,
+    last_refill: Option<std::time::Duration>,
+    is_time_missing: bool,
+    policy: QueryRateLimitPolicy,
+    accepted: u64,
+    limited: u64,
+    _marker: std::marker::PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> QueryRateLimiter<T, U> {
+    /// `capacity` is the burst size, `refill_per_second` the sustained rate
+    pub fn new(capacity: u32, refill_per_second: f64) -> Self {
+        Self {
+            capacity: capacity as f64,
+            refill_per_second,
+            tokens: capacity as f64,
+            last_refill: None,
+            is_time_missing: false,
+            policy: QueryRateLimitPolicy::default(),
+            accepted: 0,
+            limited: 0,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: QueryRateLimitPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn refill(&mut self, now: std::time::Duration) {
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.saturating_sub(last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        }
+        self.last_refill = Some(now);
+    }
+
+    /// Refills from the elapsed `Time` of the App, `None` when it has no `Time` resource
+    pub(crate) fn refill_from_time(&mut self, now: Option<std::time::Duration>) {
+        let Some(now) = now else {
+            if !self.is_time_missing {
+                warn!(
+                    "[{} -> {}]: Rate limiter has no Time resource to refill from, add the TimePlugin",
+                    std::any::type_name::<T>(),
+                    std::any::type_name::<U>()
+                );
+                self.is_time_missing = true;
+            }
+            return;
+        };
+        self.refill(now);
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.accepted += 1;
+            true
+        } else {
+            self.limited += 1;
+            false
+        }
+    }
+
+    pub fn get_policy(&self) -> QueryRateLimitPolicy {
+        self.policy
+    }
+
+    pub fn get_tokens(&self) -> f64 {
+        self.tokens
+    }
+
+    pub fn get_capacity(&self) -> f64 {
+        self.capacity
+    }
+
+    pub fn get_refill_per_second(&self) -> f64 {
+        self.refill_per_second
+    }
+
+    /// Number of goals let through by the limiter
+    pub fn get_accepted(&self) -> u64 {
+        self.accepted
+    }
+
+    /// Number of times a goal was held back or rejected by the limiter
+    pub fn get_limited(&self) -> u64 {
+        self.limited
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub enum QueryCircuitState {
+    /// Requests go through and failures are counted
+    #[default]
+    Closed,
+    /// Requests are rejected without being sent
+    Open,
+    /// A single probe request is let through to test the service
+    HalfOpen,
+}
+
+/// Sent whenever a circuit breaker changes state
+/// `service` is formatted as `request -> reply` using the type names
+#[derive(Event, Debug, Clone)]
+pub struct QueryCircuitEvent {
+    pub service: String,
+    pub from: QueryCircuitState,
+    pub to: QueryCircuitState,
+}
+
+/// Error recorded on goals that are failed fast while the circuit is open
+#[derive(Debug, Clone, Copy)]
+pub struct QueryCircuitOpenError;
+
+impl std::fmt::Display for QueryCircuitOpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Circuit breaker is open")
+    }
+}
+
+impl std::error::Error for QueryCircuitOpenError {}
+
+/// Circuit breaker for the query service serving `T` with `U`
+/// Opens after `failure_threshold` consecutive failures and half-opens once `cool_down` has elapsed
+#[derive(Resource)]
+pub struct QueryCircuitBreaker<T, U> {
+    state: QueryCircuitState,
+    failure_threshold: u32,
+    cool_down: std::time::Duration,
+    failures: u32,
+    opened_at: std::time::Duration,
+    is_probing: bool,
+    _marker: std::marker::PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> QueryCircuitBreaker<T, U> {
+    pub fn new(failure_threshold: u32, cool_down: std::time::Duration) -> Self {
+        Self {
+            state: QueryCircuitState::Closed,
+            failure_threshold,
+            cool_down,
+            failures: 0,
+            opened_at: std::time::Duration::ZERO,
+            is_probing: false,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn get_state(&self) -> QueryCircuitState {
+        self.state
+    }
+
+    pub fn get_failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Moves an open circuit to half-open once the cool-down has elapsed
+    pub fn update(&mut self, now: std::time::Duration) -> Option<QueryCircuitState> {
+        if self.state == QueryCircuitState::Open && now.saturating_sub(self.opened_at) >= self.cool_down {
+            return Some(self.transition(QueryCircuitState::HalfOpen));
+        }
+        None
+    }
+
+    pub fn allow_request(&mut self) -> bool {
+        match self.state {
+            QueryCircuitState::Closed => true,
+            QueryCircuitState::Open => false,
+            QueryCircuitState::HalfOpen => {
+                if self.is_probing {
+                    return false;
+                }
+                self.is_probing = true;
+                true
+            }
+        }
+    }
+
+    pub fn record_success(&mut self) -> Option<QueryCircuitState> {
+        self.failures = 0;
+        match self.state {
+            QueryCircuitState::Closed => None,
+            _ => Some(self.transition(QueryCircuitState::Closed)),
+        }
+    }
+
+    pub fn record_failure(&mut self, now: std::time::Duration) -> Option<QueryCircuitState> {
+        self.failures += 1;
+        match self.state {
+            QueryCircuitState::Closed if self.failures < self.failure_threshold => None,
+            QueryCircuitState::Open => None,
+            _ => {
+                self.opened_at = now;
+                Some(self.transition(QueryCircuitState::Open))
+            }
+        }
+    }
+
+    /// Returns the previous state
+    fn transition(&mut self, state: QueryCircuitState) -> QueryCircuitState {
+        self.is_probing = false;
+        std::mem::replace(&mut self.state, state)
+    }
+}
+
+/// A query handler with the same signature as `QueryServerOps::get_reply`
+pub type QueryHandler<T, U> = fn(&mut World, &QueryRequest<T>) -> Result<U>;
+
+pub enum QueryFallbackKind<T, U> {
+    /// Another handler producing the same reply type
+    Handler(QueryHandler<T, U>),
+    /// The last reply successfully produced by the primary service
+    LastGood,
+    /// A fixed reply
+    Static(U),
+}
+
+/// Fallback for the query service serving `T` with `U`
+/// Used by `run_query_server` and `run_query_client` when the primary service returns an error or times out
+#[derive(Resource)]
+pub struct QueryFallback<T, U> {
+    kind: QueryFallbackKind<T, U>,
+    timeout: Option<std::time::Duration>,
+    last_good: Option<U>,
+}
+
+impl<T, U> QueryFallback<T, U> {
+    pub fn new(kind: QueryFallbackKind<T, U>) -> Self {
+        Self { kind, timeout: None, last_good: None }
+    }
+
+    pub fn handler(handler: QueryHandler<T, U>) -> Self {
+        Self::new(QueryFallbackKind::Handler(handler))
+    }
+
+    pub fn last_good() -> Self {
+        Self::new(QueryFallbackKind::LastGood)
+    }
+
+    pub fn reply(reply: U) -> Self {
+        Self::new(QueryFallbackKind::Static(reply))
+    }
+
+    /// Fails client requests that take longer than `timeout` over to the fallback
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn get_kind(&self) -> &QueryFallbackKind<T, U> {
+        &self.kind
+    }
+
+    pub fn get_timeout(&self) -> Option<std::time::Duration> {
+        self.timeout
+    }
+
+    pub fn get_last_good(&self) -> Option<&U> {
+        self.last_good.as_ref()
+    }
+}
+
+impl<T, U: Clone> QueryFallback<T, U> {
+    pub fn record_success(&mut self, reply: &U) {
+        if matches!(self.kind, QueryFallbackKind::LastGood) {
+            self.last_good = Some(reply.clone());
+        }
+    }
+}
+
+/// Opt-in request hedging for the client service serving `T` with `U`
+/// A second identical request is sent if the first has not finished after `delay`
+/// The first to succeed is used and the other is cancelled, so the request only fails if both attempts fail
+#[derive(Resource)]
+pub struct QueryHedge<T, U> {
+    delay: std::time::Duration,
+    _marker: std::marker::PhantomData<fn() -> (T, U)>,
+}
+
+impl<T, U> QueryHedge<T, U> {
+    pub fn new(delay: std::time::Duration) -> Self {
+        Self {
+            delay,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    pub fn get_delay(&self) -> std::time::Duration {
+        self.delay
+    }
+}
+
+/// The outcome of a bridged request, sent back with its goal uuid
+pub(crate) type QueryBridgeReply<U> = (uuid::Uuid, Result<U, String>);
+
+/// A request crossing an in-process bridge, with the channel its reply is sent on
+pub(crate) struct QueryBridgeRequest<T, U> {
+    pub(crate) uuid: uuid::Uuid,
+    pub(crate) request: T,
+    pub(crate) reply: std::sync::mpsc::Sender<QueryBridgeReply<U>>,
+}
+
+/// The end of an in-process bridge in the App sending requests of `T`
+/// Pending goals are forwarded to the other App and completed with its replies by `run_query_bridge_client`
+/// Forwarded goals cancelled or despawned before their reply are cancelled in the other App too
+#[derive(Resource)]
+pub struct QueryBridgeClient<T, U> {
+    pub(crate) sender: std::sync::mpsc::Sender<QueryBridgeRequest<T, U>>,
+    pub(crate) cancel_sender: std::sync::mpsc::Sender<uuid::Uuid>,
+    pub(crate) reply_sender: std::sync::mpsc::Sender<QueryBridgeReply<U>>,
+    pub(crate) reply_receiver: std::sync::Mutex<std::sync::mpsc::Receiver<QueryBridgeReply<U>>>,
+    pub(crate) pending: std::collections::HashMap<uuid::Uuid, (Entity, QueryRequest<T>)>,
+}
+
+impl<T, U> QueryBridgeClient<T, U> {
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// The end of an in-process bridge in the App serving requests of `T`
+/// Bridged requests are spawned as goals with their original uuid by `run_query_bridge_server`
+#[derive(Resource)]
+pub struct QueryBridgeServer<T, U> {
+    pub(crate) receiver: std::sync::Mutex<std::sync::mpsc::Receiver<QueryBridgeRequest<T, U>>>,
+    pub(crate) cancel_receiver: std::sync::Mutex<std::sync::mpsc::Receiver<uuid::Uuid>>,
+    pub(crate) pending: std::collections::HashMap<uuid::Uuid, (Entity, std::sync::mpsc::Sender<QueryBridgeReply<U>>)>,
+}
+
+impl<T, U> QueryBridgeServer<T, U> {
+    pub fn get_pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Creates the two ends of an in-process bridge for the service serving `T` with `U`
+/// The client plugin goes in the App sending the requests and the server plugin in the App running the server
+pub fn query_bridge<T, U>() -> (QueryBridgeClientPlugin<T, U>, QueryBridgeServerPlugin<T, U>) {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let (reply_sender, reply_receiver) = std::sync::mpsc::channel();
+    let (cancel_sender, cancel_receiver) = std::sync::mpsc::channel();
+    let client = QueryBridgeClient {
+        sender,
+        cancel_sender,
+        reply_sender,
+        reply_receiver: std::sync::Mutex::new(reply_receiver),
+        pending: Default::default(),
+    };
+    let server = QueryBridgeServer {
+        receiver: std::sync::Mutex::new(receiver),
+        cancel_receiver: std::sync::Mutex::new(cancel_receiver),
+        pending: Default::default(),
+    };
+    (
+        QueryBridgeClientPlugin {
+            client: std::sync::Mutex::new(Some(client)),
+        },
+        QueryBridgeServerPlugin {
+            server: std::sync::Mutex::new(Some(server)),
+        },
+    )
+}
+
+/// Inserts the `QueryBridgeClient` of a bridge and runs `run_query_bridge_client`
+pub struct QueryBridgeClientPlugin<T, U> {
+    client: std::sync::Mutex<Option<QueryBridgeClient<T, U>>>,
+}
+
+impl<T, U> Plugin for QueryBridgeClientPlugin<T, U>
+where
+    T: Send + Sync + 'static + Clone,
+    U: Send + Sync + 'static + Clone,
+{
+    fn build(&self, app: &mut App) {
+        let Some(client) = self.client.lock().ok().and_then(|mut client| client.take()) else {
+            warn!("Query bridge client is already added to an App");
+            return;
+        };
+
+        app.insert_resource(client);
+        app.add_systems(Update, run_query_bridge_client::<T, U>);
+    }
+}
+
+/// Inserts the `QueryBridgeServer` of a bridge and runs `run_query_bridge_server`
+/// The App still needs a server for `T` and `U`
+pub struct QueryBridgeServerPlugin<T, U> {
+    server: std::sync::Mutex<Option<QueryBridgeServer<T, U>>>,
+}
+
+impl<T, U> Plugin for QueryBridgeServerPlugin<T, U>
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static + Clone,
+{
+    fn build(&self, app: &mut App) {
+        let Some(server) = self.server.lock().ok().and_then(|mut server| server.take()) else {
+            warn!("Query bridge server is already added to an App");
+            return;
+        };
+
+        app.insert_resource(server);
+        app.add_systems(Update, run_query_bridge_server::<T, U>.before(cleanup_requests));
+        app.add_observer(reply_removed_bridge_query::<T, U>);
+    }
+}
+
+/// Whether a service replies from the App itself or forwards its requests elsewhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub enum QueryServiceKind {
+    Server,
+    Client,
+}
+
+/// The resources configuring a service, read when the registry is listed
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryServiceConfig {
+    pub has_validator: bool,
+    pub rate_limit_capacity: Option<f64>,
+    pub rate_limit_refill_per_second: Option<f64>,
+    pub circuit_state: Option<QueryCircuitState>,
+    pub has_fallback: bool,
+    pub hedge_delay: Option<std::time::Duration>,
+}
+
+/// Counters of a service since it was registered, `in_flight` being read when the registry is listed
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryServiceStats {
+    pub completed: u64,
+    pub failed: u64,
+    pub rejected: u64,
+    pub fallbacks: u64,
+    pub in_flight: u64,
+}
+
+/// One service of the `QueryServiceRegistry`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryServiceInfo {
+    pub name: String,
+    pub request_type: String,
+    pub reply_type: String,
+    pub kind: QueryServiceKind,
+    pub config: QueryServiceConfig,
+    pub stats: QueryServiceStats,
+}
+
+struct QueryServiceEntry {
+    info: QueryServiceInfo,
+    inspect: fn(&mut World) -> (QueryServiceConfig, u64),
+}
+
+/// The services of the App, recorded by `QueryAppExt`
+/// Replies and failures are counted as the goals of a registered service complete
+#[derive(Resource, Default)]
+pub struct QueryServiceRegistry {
+    entries: Vec<QueryServiceEntry>,
+    indices: std::collections::HashMap<(std::any::TypeId, std::any::TypeId), usize>,
+}
+
+impl QueryServiceRegistry {
+    /// Records the service serving `T` with `U`, replacing a previous record of the same types
+    pub fn register<T, U>(&mut self, name: impl Into<String>, kind: QueryServiceKind) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+        U: Send + Sync + 'static,
+    {
+        let entry = QueryServiceEntry {
+            info: QueryServiceInfo {
+                name: name.into(),
+                request_type: std::any::type_name::<T>().to_string(),
+                reply_type: std::any::type_name::<U>().to_string(),
+                kind,
+                config: QueryServiceConfig::default(),
+                stats: QueryServiceStats::default(),
+            },
+            inspect: inspect_service::<T, U>,
+        };
+
+        match self.indices.get(&(std::any::TypeId::of::<T>(), std::any::TypeId::of::<U>())) {
+            Some(index) => self.entries[*index] = entry,
+            None => {
+                self.indices.insert((std::any::TypeId::of::<T>(), std::any::TypeId::of::<U>()), self.entries.len());
+                self.entries.push(entry);
+            }
+        }
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get_names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.info.name.as_str())
+    }
+
+    /// Lists the registered services in registration order, with their current configuration and stats
+    pub fn list(world: &mut World) -> Vec<QueryServiceInfo> {
+        let Some(registry) = world.get_resource::<QueryServiceRegistry>() else {
+            return Vec::new();
+        };
+
+        let entries: Vec<_> = registry.entries.iter().map(|entry| (entry.info.clone(), entry.inspect)).collect();
+        entries
+            .into_iter()
+            .map(|(mut info, inspect)| {
+                let (config, in_flight) = inspect(world);
+                info.config = config;
+                info.stats.in_flight = in_flight;
+                info
+            })
+            .collect()
+    }
+
+    pub(crate) fn record<T: 'static, U: 'static>(&mut self, is_completed: bool, is_fallback: bool) {
+        let Some(index) = self.indices.get(&(std::any::TypeId::of::<T>(), std::any::TypeId::of::<U>())) else {
+            return;
+        };
+
+        let stats = &mut self.entries[*index].info.stats;
+        match is_completed {
+            true => stats.completed += 1,
+            false => stats.failed += 1,
+        }
+
+        if is_fallback {
+            stats.fallbacks += 1;
+        }
+    }
+
+    pub(crate) fn record_rejected<T: 'static, U: 'static>(&mut self, count: u64) {
+        if let Some(index) = self.indices.get(&(std::any::TypeId::of::<T>(), std::any::TypeId::of::<U>())) {
+            self.entries[*index].info.stats.rejected += count;
+        }
+    }
+}
+
+fn inspect_service<T, U>(world: &mut World) -> (QueryServiceConfig, u64)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let limiter = world.get_resource::<QueryRateLimiter<T, U>>();
+    let config = QueryServiceConfig {
+        has_validator: world.contains_resource::<QueryValidator<T>>(),
+        rate_limit_capacity: limiter.map(|limiter| limiter.get_capacity()),
+        rate_limit_refill_per_second: limiter.map(|limiter| limiter.get_refill_per_second()),
+        circuit_state: world.get_resource::<QueryCircuitBreaker<T, U>>().map(|breaker| breaker.get_state()),
+        has_fallback: world.contains_resource::<QueryFallback<T, U>>(),
+        hedge_delay: world.get_resource::<QueryHedge<T, U>>().map(|hedge| hedge.get_delay()),
+    };
+
+    let in_flight = world
+        .query::<(&GoalComponent, &QueryRequest<T>)>()
+        .iter(world)
+        .filter(|(goal, _)| goal.accepts_reply::<U>() && !goal.is_completed() && !goal.is_failed() && !goal.is_rejected() && !goal.is_cancelled() && !goal.is_to_delete())
+        .count();
+    (config, in_flight as u64)
+}
+
+/// Request of the built-in introspection query, served by `run_query_server::<QueryServiceListRequest, QueryServiceList>`
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryServiceListRequest;
+
+/// Reply of the built-in introspection query, the services of the `QueryServiceRegistry`
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "remote", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueryServiceList {
+    pub services: Vec<QueryServiceInfo>,
+}
+
+impl QueryServerOps<QueryServiceListRequest> for QueryServiceList {
+    fn get_reply(world: &mut World, _: &QueryRequest<QueryServiceListRequest>) -> Result<Self> {
+        Ok(Self {
+            services: QueryServiceRegistry::list(world),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn rate_limiter_spends_its_burst_then_refuses() {
+        let mut limiter = QueryRateLimiter::<(), ()>::new(2, 1.0);
+        limiter.refill(Duration::ZERO);
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert_eq!(limiter.get_accepted(), 2);
+        assert_eq!(limiter.get_limited(), 1);
+    }
+
+    #[test]
+    fn rate_limiter_refills_up_to_its_capacity() {
+        let mut limiter = QueryRateLimiter::<(), ()>::new(2, 4.0);
+        limiter.refill(Duration::ZERO);
+        assert!(limiter.try_acquire());
+        assert!(limiter.try_acquire());
+
+        limiter.refill(Duration::from_millis(250));
+        assert_eq!(limiter.get_tokens(), 1.0);
+
+        limiter.refill(Duration::from_secs(10));
+        assert_eq!(limiter.get_tokens(), limiter.get_capacity());
+    }
+
+    #[test]
+    fn rate_limiter_warns_once_without_time() {
+        let mut limiter = QueryRateLimiter::<(), ()>::new(1, 1.0);
+        assert!(limiter.try_acquire());
+        limiter.refill_from_time(None);
+        assert!(limiter.is_time_missing);
+        limiter.refill_from_time(None);
+        assert_eq!(limiter.get_tokens(), 0.0);
+
+        limiter.refill_from_time(Some(Duration::ZERO));
+        limiter.refill_from_time(Some(Duration::from_secs(1)));
+        assert_eq!(limiter.get_tokens(), 1.0);
+    }
+
+    #[test]
+    fn rate_limiter_keeps_its_policy() {
+        let limiter = QueryRateLimiter::<(), ()>::new(1, 1.0);
+        assert_eq!(limiter.get_policy(), QueryRateLimitPolicy::Wait);
+        assert_eq!(limiter.with_policy(QueryRateLimitPolicy::Reject).get_policy(), QueryRateLimitPolicy::Reject);
+    }
+
+    #[test]
+    fn circuit_breaker_opens_after_consecutive_failures() {
+        let mut breaker = QueryCircuitBreaker::<(), ()>::new(2, Duration::from_secs(1));
+        assert_eq!(breaker.record_failure(Duration::ZERO), None);
+        assert_eq!(breaker.record_success(), None);
+        assert_eq!(breaker.get_failures(), 0);
+
+        assert_eq!(breaker.record_failure(Duration::ZERO), None);
+        assert_eq!(breaker.record_failure(Duration::ZERO), Some(QueryCircuitState::Closed));
+        assert_eq!(breaker.get_state(), QueryCircuitState::Open);
+        assert!(!breaker.allow_request());
+    }
+
+    #[test]
+    fn circuit_breaker_lets_a_single_probe_through_once_half_open() {
+        let mut breaker = QueryCircuitBreaker::<(), ()>::new(1, Duration::from_secs(1));
+        breaker.record_failure(Duration::from_secs(5));
+        assert_eq!(breaker.update(Duration::from_millis(5500)), None);
+        assert_eq!(breaker.update(Duration::from_secs(6)), Some(QueryCircuitState::Open));
+        assert_eq!(breaker.get_state(), QueryCircuitState::HalfOpen);
+        assert!(breaker.allow_request());
+        assert!(!breaker.allow_request());
+    }
+
+    #[test]
+    fn circuit_breaker_closes_or_reopens_after_its_probe() {
+        let mut breaker = QueryCircuitBreaker::<(), ()>::new(1, Duration::from_secs(1));
+        breaker.record_failure(Duration::ZERO);
+        breaker.update(Duration::from_secs(1));
+        assert!(breaker.allow_request());
+        assert_eq!(breaker.record_failure(Duration::from_secs(1)), Some(QueryCircuitState::HalfOpen));
+        assert_eq!(breaker.update(Duration::from_millis(1500)), None);
+
+        breaker.update(Duration::from_secs(2));
+        assert!(breaker.allow_request());
+        assert_eq!(breaker.record_success(), Some(QueryCircuitState::HalfOpen));
+        assert_eq!(breaker.get_state(), QueryCircuitState::Closed);
+        assert!(breaker.allow_request());
+    }
+
+    fn route_to_first(_: &mut World, _: &QueryRequest<i32>) -> Result<&'static str> {
+        Ok("first")
+    }
+
+    fn route_to_second(_: &mut World, _: &QueryRequest<i32>) -> Result<&'static str> {
+        Ok("second")
+    }
+
+    fn resolve_route(router: &QueryRouter<i32, &'static str>, request: i32) -> Option<&'static str> {
+        let handler = router.resolve(&request)?;
+        handler(&mut World::new(), &QueryRequest { request }).ok()
+    }
+
+    #[test]
+    fn router_resolves_the_first_matching_route() {
+        let mut router = QueryRouter::new();
+        router.add_route(|request| *request > 0, route_to_first).add_route(|request| *request > 10, route_to_second);
+        assert_eq!(resolve_route(&router, 20), Some("first"));
+        assert_eq!(resolve_route(&router, -1), None);
+    }
+
+    #[test]
+    fn router_resolves_keyed_routes_with_its_key() {
+        let mut router = QueryRouter::new();
+        router.add_keyed_route("even", route_to_first).add_keyed_route("odd", route_to_second);
+        assert_eq!(resolve_route(&router, 3), None);
+
+        router.set_key(|request| if request % 2 == 0 { "even" } else { "odd" }.to_string());
+        assert_eq!(resolve_route(&router, 3), Some("second"));
+        assert_eq!(resolve_route(&router, 4), Some("first"));
+        assert_eq!(router.get_route_count(), 2);
+    }
+}
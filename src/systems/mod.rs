@@ -17,21 +17,54 @@
 */
 // =========================================================================
 use super::*;
+use bevy_ecs::world::DeferredWorld;
 
 /// A system that listens to query requests
+/// Requests asking for another reply content are ignored, the others are bound to `U`
+/// A request without reply content is bound to `U` if it is the only reply content of the endpoints of `T`
+/// Otherwise it is refused with a warning, since the endpoint claiming it would depend on system order
+/// If a `QueryValidator<T>` resource is present, requests failing validation are spawned as rejected goals
+/// The `QueryReply<U>` is only inserted once the goal completes
 /// `T` is the query request content
 /// `U` is the query reply content
-pub fn spawn_request_endpoint<T, U>(mut commands: Commands, mut events: EventReader<QueryEvent<T>>)
+pub fn spawn_request_endpoint<T, U>(_registration: Local<QueryEndpointRegistration<T, U>>, endpoints: Option<Res<QueryEndpoints<T>>>, mut commands: Commands, mut events: EventMutator<QueryEvent<T>>)
 where
     T: Clone + Send + Sync + 'static,
-    U: Default + Send + Sync + 'static,
+    U: Send + Sync + 'static,
 {
     for event in events.read() {
-        commands.spawn((GoalComponent::new(event.uuid), QueryRequest { request: event.request.clone() }, QueryReply::<U>::default()));
-        info!("[{:?}]: Request spawned", event.uuid);
+        if event.claim_reply::<U>(endpoints.as_deref()) {
+            queue_request::<T, U>(&mut commands, event.clone());
+        }
     }
 }
 
+/// An observer that spawns the goal as soon as a `QueryEvent<T>` is triggered with `commands.trigger`
+/// Behaves like `spawn_request_endpoint`, without waiting for the event reader to run
+/// `T` is the query request content
+/// `U` is the query reply content
+pub fn observe_request_endpoint<T, U>(mut trigger: Trigger<QueryEvent<T>>, _registration: Local<QueryEndpointRegistration<T, U>>, endpoints: Option<Res<QueryEndpoints<T>>>, mut commands: Commands)
+where
+    T: Clone + Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let event = trigger.event_mut();
+    if event.claim_reply::<U>(endpoints.as_deref()) {
+        queue_request::<T, U>(&mut commands, event.clone());
+    }
+}
+
+/// Spawns the goal through `spawn_request` once the commands are applied, so every intake path follows the same rules
+fn queue_request<T, U>(commands: &mut Commands, event: QueryEvent<T>)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    commands.queue(move |world: &mut World| {
+        spawn_request::<T, U>(world, event);
+    });
+}
+
 /// `T` is the query request content
 /// `U` is the query reply content
 pub fn run_query_server<T, U>(world: &mut World)
@@ -39,76 +72,545 @@ where
     T: Send + Sync + 'static + Clone,
     U: QueryServerOps<T> + Send + Sync + 'static + Clone,
 {
+    serve_queries::<T, U>(world, U::get_reply);
+}
+
+/// Same as `run_query_server`, but each request is dispatched to a handler of the `QueryRouter<T, U>` resource
+/// Requests without a matching route fail
+/// `T` is the query request content
+/// `U` is the query reply content
+pub fn run_query_router<T, U>(world: &mut World)
+where
+    T: Send + Sync + 'static + Clone,
+    U: Send + Sync + 'static + Clone,
+{
+    serve_queries::<T, U>(world, |world, request| {
+        let handler = world.get_resource::<QueryRouter<T, U>>().and_then(|router| router.resolve(&request.request));
+        match handler {
+            Some(handler) => handler(world, request),
+            None => Err(anyhow::anyhow!("No route for request of {}", std::any::type_name::<T>())),
+        }
+    });
+}
+
+/// Same as `run_query_server`, but the handler mutates the world through a `QueryTransaction`
+/// Every change recorded by the transaction is reverted if the handler returns an error
+/// `T` is the query request content
+/// `U` is the query reply content
+pub fn run_transactional_query_server<T, U>(world: &mut World)
+where
+    T: Send + Sync + 'static + Clone,
+    U: QueryTransactionalOps<T> + Send + Sync + 'static + Clone,
+{
+    serve_queries::<T, U>(world, |world, request| {
+        let mut transaction = QueryTransaction::new(world);
+        let result = U::get_reply(&mut transaction, request);
+        match result.is_ok() {
+            true => transaction.commit(),
+            false => transaction.rollback(),
+        }
+        result
+    });
+}
+
+fn serve_queries<T, U>(world: &mut World, handler: impl Fn(&mut World, &QueryRequest<T>) -> Result<U>)
+where
+    T: Send + Sync + 'static + Clone,
+    U: Send + Sync + 'static + Clone,
+{
+    let now = world.get_resource::<Time>().map(|time| time.elapsed());
+    let mut limiter = world.remove_resource::<QueryRateLimiter<T, U>>();
+    if let Some(limiter) = limiter.as_mut() {
+        limiter.refill_from_time(now);
+    }
+
     let mut entities = Vec::new();
+    let mut rejected = 0;
     let mut query_queries = world.query_filtered::<(Entity, &mut GoalComponent, &QueryRequest<T>), (With<GoalComponent>, With<QueryRequest<T>>)>();
 
     for (entity, mut goal, request) in query_queries.iter_mut(world) {
-        if goal.is_executing() {
-            debug!("[{:?}]: Goal is already executing", goal.get_uuid());
+        if !goal.accepts_reply::<U>() || !is_goal_pending(&goal) {
             continue;
         }
 
-        if goal.is_completed() {
-            debug!("[{:?}]: Goal is already completed", goal.get_uuid());
+        if let Some(limiter) = limiter.as_mut() {
+            if !acquire_rate_limit(limiter, &mut goal) {
+                rejected += goal.is_rejected() as u64;
+                continue;
+            }
+        }
+
+        goal.mark_executing();
+
+        entities.push((entity, goal.clone(), request.clone()));
+    }
+
+    if let Some(limiter) = limiter {
+        world.insert_resource(limiter);
+    }
+    record_rejections::<T, U>(world, rejected);
+
+    for (entity, goal, request) in entities {
+        let result = handler(world, &request);
+        complete_goal::<T, U>(world, entity, goal, &request, result);
+    }
+}
+
+/// `T` is the query request content
+/// `U` is the query reply content, built from sub-requests by `QueryAggregateOps`
+pub fn run_query_aggregate<T, U>(world: &mut World)
+where
+    T: Send + Sync + 'static + Clone,
+    U: QueryAggregateOps<T> + Send + Sync + 'static + Clone,
+{
+    let mut started = Vec::new();
+    let mut waiting = Vec::new();
+    let mut query_queries = world.query_filtered::<(Entity, &mut GoalComponent, &QueryRequest<T>, Option<&QuerySubRequests>), (With<GoalComponent>, With<QueryRequest<T>>)>();
+
+    for (entity, mut goal, request, sub_requests) in query_queries.iter_mut(world) {
+        if !goal.accepts_reply::<U>() {
             continue;
         }
 
-        if goal.is_to_delete() {
-            debug!("[{:?}]: Goal is marked for deletion", goal.get_uuid());
+        if let (true, Some(sub_requests)) = (goal.is_executing(), sub_requests) {
+            waiting.push((entity, goal.clone(), request.clone(), sub_requests.get_entities().to_vec()));
+            continue;
+        }
+
+        if !is_goal_pending(&goal) {
             continue;
         }
 
         goal.mark_executing();
 
-        entities.push((entity, goal.clone(), request.clone()));
+        started.push((entity, goal.clone(), request.clone()));
     }
 
-    for (entity, goal, request) in entities.iter_mut() {
-        match U::get_reply(world, request) {
-            Ok(reply) => {
-                info!("[{:?}]: Goal is completed", goal.get_uuid());
-                goal.mark_completed();
-                world.get_entity_mut(*entity).unwrap().insert((goal.clone(), QueryReply { reply: reply }));
+    for (entity, goal, request) in started {
+        world.entity_mut(entity).insert(QuerySubRequests::default());
+        if let Err(e) = U::spawn_children(world, entity, &request) {
+            cancel_sub_requests(world, entity);
+            complete_goal::<T, U>(world, entity, goal, &request, Err(e));
+        }
+    }
+
+    for (entity, goal, request, children) in waiting {
+        match get_sub_requests_state(world, &children) {
+            SubRequestsState::Waiting => {}
+            SubRequestsState::Completed => {
+                let result = U::aggregate(world, &request, &children);
+                complete_goal::<T, U>(world, entity, goal, &request, result);
+            }
+            SubRequestsState::Failed(reason) => {
+                cancel_sub_requests(world, entity);
+                complete_goal::<T, U>(world, entity, goal, &request, Err(anyhow::anyhow!(reason)));
+            }
+        }
+    }
+}
+
+/// Drives the goals spawned by `QueryChain`, one step at a time
+pub fn run_query_chains(world: &mut World) {
+    let mut chains = Vec::new();
+    let mut query_chains = world.query::<(Entity, &mut GoalComponent, &QueryChainState, Option<&QuerySubRequests>)>();
+
+    for (entity, mut goal, chain, sub_requests) in query_chains.iter_mut(world) {
+        if goal.is_executing() {
+            let previous = sub_requests.and_then(|sub_requests| sub_requests.get_entities().last().copied());
+            chains.push((entity, goal.get_uuid(), chain.clone(), previous));
+            continue;
+        }
+
+        if !is_goal_pending(&goal) {
+            continue;
+        }
+
+        goal.mark_executing();
+
+        chains.push((entity, goal.get_uuid(), chain.clone(), None));
+    }
+
+    for (entity, uuid, mut chain, previous) in chains {
+        let result = match previous {
+            None => chain.run_step(world, entity, None).map(|_| false),
+            Some(previous) => match get_sub_requests_state(world, &[previous]) {
+                SubRequestsState::Waiting => continue,
+                SubRequestsState::Failed(reason) => Err(anyhow::anyhow!(reason)),
+                SubRequestsState::Completed if chain.get_step() < chain.get_len() => chain.run_step(world, entity, Some(previous)).map(|_| false),
+                SubRequestsState::Completed => chain.finish(world, entity, previous).map(|_| true),
+            },
+        };
+
+        match result {
+            Ok(false) => {
+                chain.advance();
+                debug!("[{:?}]: Chain step {}/{} started", uuid, chain.get_step(), chain.get_len());
+                world.entity_mut(entity).insert(chain);
+            }
+            Ok(true) => {
+                info!("[{:?}]: Goal is completed", uuid);
+                if let Some(mut goal) = world.get_mut::<GoalComponent>(entity) {
+                    goal.mark_completed();
+                }
             }
             Err(e) => {
-                error!("[{:?}]: {}", goal.get_uuid(), e);
-                goal.mark_to_delete();
+                error!("[{:?}]: {}", uuid, e);
+                cancel_sub_requests(world, entity);
+                if let Some(mut goal) = world.get_mut::<GoalComponent>(entity) {
+                    goal.mark_failed(e.to_string());
+                }
             }
         }
     }
 }
 
+/// Drives the goals spawned by `QueryJoin`
+pub fn run_query_joins(world: &mut World) {
+    let mut joins = Vec::new();
+    let mut query_joins = world.query::<(Entity, &mut GoalComponent, &QueryJoinState, &QuerySubRequests)>();
+
+    for (entity, mut goal, join, sub_requests) in query_joins.iter_mut(world) {
+        if !goal.is_executing() {
+            if !is_goal_pending(&goal) {
+                continue;
+            }
+            goal.mark_executing();
+        }
+
+        joins.push((entity, goal.get_uuid(), join.get_mode(), sub_requests.get_entities().to_vec()));
+    }
+
+    for (entity, uuid, mode, children) in joins {
+        let result = match mode {
+            QueryJoinMode::All => match get_sub_requests_state(world, &children) {
+                SubRequestsState::Waiting => continue,
+                SubRequestsState::Completed => Ok(None),
+                SubRequestsState::Failed(reason) => Err(reason),
+            },
+            QueryJoinMode::Race => {
+                let winner = children.iter().find(|child| matches!(get_sub_requests_state(world, &[**child]), SubRequestsState::Completed));
+                let is_failed = children.iter().all(|child| matches!(get_sub_requests_state(world, &[*child]), SubRequestsState::Failed(_)));
+                match (winner, is_failed) {
+                    (Some(winner), _) => Ok(Some(*winner)),
+                    (None, true) => Err("Every request of the race failed".to_string()),
+                    (None, false) => continue,
+                }
+            }
+        };
+
+        cancel_sub_requests(world, entity);
+        let mut entity = world.entity_mut(entity);
+        match result {
+            Ok(winner) => {
+                if let (Some(winner), Some(mut join)) = (winner, entity.get_mut::<QueryJoinState>()) {
+                    join.mark_winner(winner);
+                }
+                info!("[{:?}]: Goal is completed", uuid);
+                if let Some(mut goal) = entity.get_mut::<GoalComponent>() {
+                    goal.mark_completed();
+                }
+            }
+            Err(reason) => {
+                error!("[{:?}]: {}", uuid, reason);
+                if let Some(mut goal) = entity.get_mut::<GoalComponent>() {
+                    goal.mark_failed(reason);
+                }
+            }
+        }
+    }
+}
+
+/// Drives the goals spawned by `QuerySaga`
+pub fn run_query_sagas(world: &mut World) {
+    let mut sagas = Vec::new();
+    let mut query_sagas = world.query::<(Entity, &mut GoalComponent, &QuerySagaState)>();
+
+    for (entity, mut goal, saga) in query_sagas.iter_mut(world) {
+        if !goal.is_executing() {
+            if !is_goal_pending(&goal) {
+                continue;
+            }
+            goal.mark_executing();
+        }
+
+        sagas.push((entity, goal.get_uuid(), saga.clone()));
+    }
+
+    for (entity, uuid, mut saga) in sagas {
+        let state = match saga.get_current() {
+            Some(current) => get_sub_requests_state(world, &[current]),
+            None => SubRequestsState::Completed,
+        };
+
+        match (saga.get_phase(), state) {
+            (_, SubRequestsState::Waiting) => continue,
+            (QuerySagaPhase::Running, SubRequestsState::Completed) => {
+                if saga.get_current().is_some() {
+                    saga.complete_step();
+                }
+                saga.start_next_step(world, entity);
+            }
+            (QuerySagaPhase::Running, SubRequestsState::Failed(reason)) => {
+                warn!("[{:?}]: Saga step {} failed, compensating: {}", uuid, saga.get_completed_steps() + 1, reason);
+                saga.start_compensation(reason);
+                saga.start_next_compensation(world, entity);
+            }
+            (QuerySagaPhase::Compensating, state) => {
+                if let SubRequestsState::Failed(reason) = &state {
+                    error!("[{:?}]: Saga compensation failed: {}", uuid, reason);
+                }
+                saga.complete_compensation(matches!(state, SubRequestsState::Completed));
+                saga.start_next_compensation(world, entity);
+            }
+            (QuerySagaPhase::Completed | QuerySagaPhase::Compensated, _) => {}
+        }
+
+        let phase = saga.get_phase();
+        let failure = saga.get_failure().map(|failure| failure.to_string());
+        let mut entity = world.entity_mut(entity);
+        entity.insert(saga);
+        let Some(mut goal) = entity.get_mut::<GoalComponent>() else {
+            continue;
+        };
+
+        match phase {
+            QuerySagaPhase::Completed => {
+                info!("[{:?}]: Goal is completed", uuid);
+                goal.mark_completed();
+            }
+            QuerySagaPhase::Compensated => {
+                let reason = format!("Saga was compensated: {}", failure.unwrap_or_default());
+                error!("[{:?}]: {}", uuid, reason);
+                goal.mark_failed(reason);
+            }
+            QuerySagaPhase::Running | QuerySagaPhase::Compensating => {}
+        }
+    }
+}
+
+/// Spawns a sub-request as a child of the `parent` goal
+/// The sub-request goes through the same validation and server or client systems as any other request of `T`
+pub fn spawn_child_request<T, U>(world: &mut World, parent: Entity, request: T) -> Entity
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let goal = new_goal::<T, U>(world, uuid::Uuid::new_v4(), &request);
+    let uuid = goal.get_uuid();
+    let child = world.spawn((goal, QueryRequest { request })).id();
+    let mut parent = world.entity_mut(parent);
+    parent.add_child(child);
+    match parent.get_mut::<QuerySubRequests>() {
+        Some(mut sub_requests) => sub_requests.push(child),
+        None => {
+            let mut sub_requests = QuerySubRequests::default();
+            sub_requests.push(child);
+            parent.insert(sub_requests);
+        }
+    }
+    info!("[{:?}]: Sub-request spawned", uuid);
+    child
+}
+
+/// Spawns the goal of `event` straight into the world, as `spawn_request_endpoint` would
+pub fn spawn_request<T, U>(world: &mut World, event: QueryEvent<T>) -> Option<Entity>
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    if !event.accepts_reply::<U>() {
+        return None;
+    }
+
+    let goal = new_goal::<T, U>(world, event.uuid, &event.request);
+    let entity = world.spawn((goal, QueryRequest { request: event.request })).id();
+    info!("[{:?}]: Request spawned", event.uuid);
+    Some(entity)
+}
+
+/// The reply of a finished goal or the reason it did not complete, `None` while it is still running
+pub(crate) fn get_goal_outcome<U>(world: &World, entity: Entity) -> Option<Result<&U, String>>
+where
+    U: Send + Sync + 'static,
+{
+    let Some(goal) = world.get::<GoalComponent>(entity) else {
+        return Some(Err("Request was despawned before completion".to_string()));
+    };
+
+    if goal.is_completed() {
+        let reply = world.get::<QueryReply<U>>(entity)?;
+        return Some(Ok(&reply.reply));
+    }
+
+    if goal.is_failed() || goal.is_rejected() || goal.is_cancelled() || goal.is_to_delete() {
+        return Some(Err(goal.get_reason().unwrap_or("Goal was cancelled").to_string()));
+    }
+
+    None
+}
+
+/// Builds a goal bound to `U`, rejected if the `QueryValidator<T>` resource refuses the request
+pub(crate) fn new_goal<T, U>(world: &mut World, uuid: uuid::Uuid, request: &T) -> GoalComponent
+where
+    T: Send + Sync + 'static,
+    U: 'static,
+{
+    let mut goal = GoalComponent::new(uuid).with_reply::<U>();
+    let Some(Err(e)) = world.get_resource::<QueryValidator<T>>().map(|validator| validator.validate(request)) else {
+        return goal;
+    };
+
+    warn!("[{:?}]: Request rejected: {}", uuid, e);
+    goal.mark_rejected(e.to_string());
+    record_rejections::<T, U>(world, 1);
+    goal
+}
+
+/// Runs the callbacks attached with `commands.query` once their goal has finished
+/// Should run before `cleanup_requests`, which despawns failed goals
+pub fn run_query_callbacks<U>(world: &mut World)
+where
+    U: Clone + Send + Sync + 'static,
+{
+    let mut finished = Vec::new();
+    let mut query_callbacks = world.query::<(Entity, &GoalComponent, &QueryCallbacks<U>, Option<&QueryReply<U>>)>();
+
+    for (entity, goal, callbacks, reply) in query_callbacks.iter(world) {
+        let outcome = match reply {
+            Some(reply) if goal.is_completed() => Ok(reply.clone()),
+            _ if goal.is_failed() || goal.is_rejected() || goal.is_cancelled() || goal.is_to_delete() => Err(goal.get_reason().unwrap_or("Goal was cancelled").to_string()),
+            _ => continue,
+        };
+        finished.push((entity, goal.get_uuid(), callbacks.clone(), outcome));
+    }
+
+    for (entity, uuid, callbacks, outcome) in finished {
+        let result = match (outcome, callbacks.get_on_reply(), callbacks.get_on_error()) {
+            (Ok(reply), Some(on_reply), _) => world.run_system_with_input(on_reply, reply).map_err(|e| e.to_string()),
+            (Err(reason), _, Some(on_error)) => world.run_system_with_input(on_error, reason).map_err(|e| e.to_string()),
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            error!("[{:?}]: Callback failed: {}", uuid, e);
+        }
+
+        if let Ok(mut entity) = world.get_entity_mut(entity) {
+            entity.remove::<QueryCallbacks<U>>();
+        }
+
+        // A callback despawning its own goal cannot be unregistered by the observer while it runs
+        if let Some(on_reply) = callbacks.get_on_reply() {
+            let _ = world.unregister_system(on_reply);
+        }
+        if let Some(on_error) = callbacks.get_on_error() {
+            let _ = world.unregister_system(on_error);
+        }
+    }
+}
+
+/// Unregisters the one-shot systems of a `QueryCallbacks<U>` when it is removed
+/// Observes each goal given callbacks by `commands.query`, so goals despawned before finishing do not leak their callbacks
+pub fn unregister_query_callbacks<U>(trigger: Trigger<OnRemove, QueryCallbacks<U>>, mut world: DeferredWorld)
+where
+    U: Send + Sync + 'static,
+{
+    let Some(callbacks) = world.get::<QueryCallbacks<U>>(trigger.entity()).cloned() else {
+        return;
+    };
+
+    let mut commands = world.commands();
+    if let Some(on_reply) = callbacks.get_on_reply() {
+        commands.unregister_system(on_reply);
+    }
+    if let Some(on_error) = callbacks.get_on_error() {
+        commands.unregister_system(on_error);
+    }
+}
+
+/// Cancels the descendants of cancelled goals
+pub fn cascade_cancellation(mut goals: Query<(Entity, &mut GoalComponent)>, children: Query<&Children>) {
+    let mut descendants = Vec::new();
+    for (entity, goal) in goals.iter() {
+        if goal.is_cancelled() {
+            descendants.extend(children.iter_descendants(entity));
+        }
+    }
+
+    for entity in descendants {
+        let Ok((_, mut goal)) = goals.get_mut(entity) else {
+            continue;
+        };
+
+        if goal.is_cancelled() || goal.is_completed() {
+            continue;
+        }
+
+        goal.mark_cancelled();
+        info!("[{:?}]: Goal is cancelled by its parent", goal.get_uuid());
+    }
+}
+
 /// Garbage collection for query requests
+/// Sub-requests are despawned together with their parent
 /// todo: implement a timeout check
 pub fn cleanup_requests(mut commands: Commands, queries: Query<(Entity, &GoalComponent), With<GoalComponent>>) {
     for (entity, goal) in queries.iter() {
         if goal.is_to_delete() {
-            commands.entity(entity).despawn();
+            commands.entity(entity).try_despawn_recursive();
             info!("[{:?}]: Request despawned", goal.get_uuid());
         }
     }
 }
 
 use bevy_tokio_tasks::TokioTasksRuntime;
-pub fn run_query_client<T, U>(runtime: ResMut<TokioTasksRuntime>, mut query_queries: Query<(Entity, &mut GoalComponent, &QueryRequest<T>), (With<GoalComponent>, With<QueryRequest<T>>)>)
-where
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn run_query_client<T, U>(
+    runtime: ResMut<TokioTasksRuntime>,
+    time: Option<Res<Time>>,
+    mut limiter: Option<ResMut<QueryRateLimiter<T, U>>>,
+    mut breaker: Option<ResMut<QueryCircuitBreaker<T, U>>>,
+    mut circuit_events: Option<ResMut<Events<QueryCircuitEvent>>>,
+    fallback: Option<Res<QueryFallback<T, U>>>,
+    hedge: Option<Res<QueryHedge<T, U>>>,
+    registry: Option<ResMut<QueryServiceRegistry>>,
+    mut query_queries: Query<(Entity, &mut GoalComponent, &QueryRequest<T>), (With<GoalComponent>, With<QueryRequest<T>>)>,
+) where
     T: Send + Sync + 'static + Clone,
     U: QueryClientOps<T> + Send + Sync + 'static + Clone,
 {
+    let now = time.map(|time| time.elapsed());
+    if let Some(limiter) = limiter.as_mut() {
+        limiter.refill_from_time(now);
+    }
+
+    if let (Some(breaker), Some(now)) = (breaker.as_mut(), now) {
+        if let Some(from) = breaker.update(now) {
+            send_circuit_event::<T, U>(circuit_events.as_deref_mut(), from, breaker.get_state());
+        }
+    }
+
     let mut entities = Vec::new();
+    let mut rejected = 0;
     for (entity, mut goal, request) in query_queries.iter_mut() {
-        if goal.is_executing() {
-            debug!("[{:?}]: Goal is already executing", goal.get_uuid());
+        if !goal.accepts_reply::<U>() || !is_goal_pending(&goal) {
             continue;
         }
 
-        if goal.is_completed() {
-            debug!("[{:?}]: Goal is already completed", goal.get_uuid());
-            continue;
+        if let Some(limiter) = limiter.as_mut() {
+            if !acquire_rate_limit(limiter, &mut goal) {
+                rejected += goal.is_rejected() as u64;
+                continue;
+            }
         }
-        if goal.is_to_delete() {
-            debug!("[{:?}]: Goal is marked for deletion", goal.get_uuid());
-            continue;
+
+        if let Some(breaker) = breaker.as_mut() {
+            if !breaker.allow_request() {
+                warn!("[{:?}]: Request rejected: {}", goal.get_uuid(), QueryCircuitOpenError);
+                goal.mark_rejected(QueryCircuitOpenError.to_string());
+                rejected += 1;
+                continue;
+            }
         }
 
         goal.mark_executing();
@@ -116,29 +618,438 @@ where
         entities.push((entity, goal.clone(), request.clone()));
     }
 
-    for (entity, goal, request) in entities.iter_mut() {
-        let mut goal = goal.clone();
-        let entity_clone = entity.clone();
-        let request = request.clone();
+    if let (Some(mut registry), true) = (registry, rejected > 0) {
+        registry.record_rejected::<T, U>(rejected);
+    }
+
+    let timeout = fallback.and_then(|fallback| fallback.get_timeout());
+    let hedge_delay = hedge.map(|hedge| hedge.get_delay());
+    for (entity, mut goal, request) in entities {
         runtime.spawn_background_task(move |mut ctx| async move {
-            match U::send_request(&mut ctx, &request).await {
-                Ok(reply) => {
-                    ctx.run_on_main_thread(move |ctx| {
-                        info!("[{:?}]: Goal is completed", goal.get_uuid());
-                        goal.mark_completed();
-                        ctx.world.get_entity_mut(entity_clone).unwrap().insert((goal.clone(), QueryReply { reply: reply }));
-                    })
-                    .await;
+            let send = async {
+                match hedge_delay {
+                    Some(delay) => {
+                        let (result, attempt) = send_hedged_request::<T, U>(&ctx, &request, delay).await;
+                        goal.mark_winning_attempt(attempt);
+                        result
+                    }
+                    None => U::send_request(&mut ctx, &request).await,
+                }
+            };
+
+            let result = match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, send).await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow::anyhow!("Request timed out after {:?}", timeout)),
+                },
+                None => send.await,
+            };
+
+            ctx.run_on_main_thread(move |ctx| {
+                record_circuit_result::<T, U>(ctx.world, result.is_ok());
+                complete_goal::<T, U>(ctx.world, entity, goal, &request, result);
+            })
+            .await;
+        });
+    }
+}
+
+/// Sends the request, and a second copy if the first has not finished after `delay`
+/// Returns the first successful attempt along with its attempt number, or the last failure if both fail
+async fn send_hedged_request<T, U>(ctx: &bevy_tokio_tasks::TaskContext, request: &QueryRequest<T>, delay: std::time::Duration) -> (Result<U>, u32)
+where
+    U: QueryClientOps<T>,
+{
+    let mut first_ctx = ctx.clone();
+    let first = U::send_request(&mut first_ctx, request);
+    tokio::pin!(first);
+
+    tokio::select! {
+        result = &mut first => return (result, 1),
+        _ = tokio::time::sleep(delay) => {}
+    }
+
+    debug!("First attempt did not finish within {:?}, sending a hedged request", delay);
+    let mut second_ctx = ctx.clone();
+    let second = U::send_request(&mut second_ctx, request);
+    tokio::pin!(second);
+
+    // A failed attempt leaves the other one running, and the losing future is dropped here, which cancels it
+    tokio::select! {
+        result = &mut first => match result {
+            Ok(reply) => (Ok(reply), 1),
+            Err(e) => {
+                debug!("First attempt failed, waiting for the hedged request: {}", e);
+                (second.await, 2)
+            }
+        },
+        result = &mut second => match result {
+            Ok(reply) => (Ok(reply), 2),
+            Err(e) => {
+                debug!("Hedged request failed, waiting for the first attempt: {}", e);
+                (first.await, 1)
+            }
+        },
+    }
+}
+
+/// Writes the outcome of a goal back to its entity
+/// Failures are handed to the `QueryFallback<T, U>` resource if there is one, unless the goal was cancelled or despawned meanwhile
+fn complete_goal<T, U>(world: &mut World, entity: Entity, mut goal: GoalComponent, request: &QueryRequest<T>, result: Result<U>)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static + Clone,
+{
+    let Ok(current) = world.get_entity(entity) else {
+        warn!("[{:?}]: Request was despawned before completion", goal.get_uuid());
+        return;
+    };
+
+    if current.get::<GoalComponent>().is_some_and(|current| current.is_cancelled() || current.is_to_delete()) {
+        debug!("[{:?}]: Goal was cancelled before completion, discarding the reply", goal.get_uuid());
+        return;
+    }
+
+    let reply = match result {
+        Ok(reply) => {
+            if let Some(mut fallback) = world.get_resource_mut::<QueryFallback<T, U>>() {
+                fallback.record_success(&reply);
+            }
+            Ok(reply)
+        }
+        Err(e) => {
+            error!("[{:?}]: {}", goal.get_uuid(), e);
+            match resolve_fallback::<T, U>(world, request) {
+                Some(Ok(reply)) => {
+                    warn!("[{:?}]: Reply is provided by the fallback", goal.get_uuid());
+                    goal.mark_fallback(e.to_string());
+                    Ok(reply)
                 }
-                Err(e) => {
-                    ctx.run_on_main_thread(move |ctx| {
-                        error!("[{:?}]: {}", goal.get_uuid(), e);
-                        goal.mark_to_delete();
-                        ctx.world.get_entity_mut(entity_clone).unwrap().insert(goal.clone());
-                    })
-                    .await;
+                Some(Err(fallback_error)) => {
+                    error!("[{:?}]: Fallback failed: {}", goal.get_uuid(), fallback_error);
+                    Err(e.to_string())
                 }
+                None => Err(e.to_string()),
+            }
+        }
+    };
+
+    // A fallback handler may have despawned the goal
+    let Ok(mut entity) = world.get_entity_mut(entity) else {
+        warn!("[{:?}]: Request was despawned before completion", goal.get_uuid());
+        return;
+    };
+
+    let is_fallback = goal.is_fallback();
+    let is_completed = match reply {
+        Ok(reply) => {
+            info!("[{:?}]: Goal is completed", goal.get_uuid());
+            goal.mark_completed();
+            entity.insert((goal, QueryReply { reply }));
+            true
+        }
+        Err(reason) => {
+            goal.mark_failed(reason);
+            entity.insert(goal);
+            false
+        }
+    };
+
+    if let Some(mut registry) = world.get_resource_mut::<QueryServiceRegistry>() {
+        registry.record::<T, U>(is_completed, is_fallback);
+    }
+}
+
+fn resolve_fallback<T, U>(world: &mut World, request: &QueryRequest<T>) -> Option<Result<U>>
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static + Clone,
+{
+    let fallback = world.get_resource::<QueryFallback<T, U>>()?;
+    match fallback.get_kind() {
+        QueryFallbackKind::Handler(handler) => {
+            let handler = *handler;
+            Some(handler(world, request))
+        }
+        QueryFallbackKind::LastGood => fallback.get_last_good().cloned().map(Ok),
+        QueryFallbackKind::Static(reply) => Some(Ok(reply.clone())),
+    }
+}
+
+enum SubRequestsState {
+    Waiting,
+    Completed,
+    Failed(String),
+}
+
+fn get_sub_requests_state(world: &World, children: &[Entity]) -> SubRequestsState {
+    let mut state = SubRequestsState::Completed;
+    for child in children {
+        let Some(goal) = world.get::<GoalComponent>(*child) else {
+            return SubRequestsState::Failed(format!("Sub-request {:?} was despawned", child));
+        };
+
+        if goal.is_completed() {
+            continue;
+        }
+
+        if goal.is_rejected() || goal.is_failed() || goal.is_cancelled() || goal.is_to_delete() {
+            return SubRequestsState::Failed(format!("Sub-request [{:?}] failed: {}", goal.get_uuid(), goal.get_reason().unwrap_or("unknown reason")));
+        }
+
+        state = SubRequestsState::Waiting;
+    }
+    state
+}
+
+fn cancel_sub_requests(world: &mut World, parent: Entity) {
+    let Some(sub_requests) = world.get::<QuerySubRequests>(parent) else {
+        return;
+    };
+
+    for child in sub_requests.get_entities().to_vec() {
+        if let Some(mut goal) = world.get_mut::<GoalComponent>(child) {
+            if !goal.is_completed() && !goal.is_cancelled() {
+                goal.mark_cancelled();
+                info!("[{:?}]: Goal is cancelled by its parent", goal.get_uuid());
             }
+        }
+    }
+}
+
+fn is_goal_pending(goal: &GoalComponent) -> bool {
+    if goal.is_executing() {
+        debug!("[{:?}]: Goal is already executing", goal.get_uuid());
+        return false;
+    }
+
+    if goal.is_completed() {
+        debug!("[{:?}]: Goal is already completed", goal.get_uuid());
+        return false;
+    }
+
+    if goal.is_rejected() {
+        debug!("[{:?}]: Goal was rejected", goal.get_uuid());
+        return false;
+    }
+
+    if goal.is_cancelled() {
+        debug!("[{:?}]: Goal was cancelled", goal.get_uuid());
+        return false;
+    }
+
+    if goal.is_to_delete() {
+        debug!("[{:?}]: Goal is marked for deletion", goal.get_uuid());
+        return false;
+    }
+
+    true
+}
+
+/// Returns `true` if the goal may start executing, applying the limiter policy otherwise
+fn acquire_rate_limit<T, U>(limiter: &mut QueryRateLimiter<T, U>, goal: &mut GoalComponent) -> bool {
+    if limiter.try_acquire() {
+        return true;
+    }
+
+    match limiter.get_policy() {
+        QueryRateLimitPolicy::Wait => {
+            debug!("[{:?}]: Goal is waiting for the rate limiter", goal.get_uuid());
+        }
+        QueryRateLimitPolicy::Reject => {
+            warn!("[{:?}]: Request rejected: rate limit exceeded", goal.get_uuid());
+            goal.mark_rejected("Rate limit exceeded");
+        }
+    }
+    false
+}
+
+fn record_rejections<T, U>(world: &mut World, count: u64)
+where
+    T: 'static,
+    U: 'static,
+{
+    if let (Some(mut registry), true) = (world.get_resource_mut::<QueryServiceRegistry>(), count > 0) {
+        registry.record_rejected::<T, U>(count);
+    }
+}
+
+fn record_circuit_result<T, U>(world: &mut World, is_success: bool)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static,
+{
+    let now = world.get_resource::<Time>().map(|time| time.elapsed()).unwrap_or_default();
+    let Some(mut breaker) = world.get_resource_mut::<QueryCircuitBreaker<T, U>>() else {
+        return;
+    };
+
+    let transition = match is_success {
+        true => breaker.record_success(),
+        false => breaker.record_failure(now),
+    };
+
+    if let Some(from) = transition {
+        let to = breaker.get_state();
+        let events = world.get_resource_mut::<Events<QueryCircuitEvent>>();
+        send_circuit_event::<T, U>(events.map(|events| events.into_inner()), from, to);
+    }
+}
+
+fn send_circuit_event<T, U>(events: Option<&mut Events<QueryCircuitEvent>>, from: QueryCircuitState, to: QueryCircuitState) {
+    let service = format!("{} -> {}", std::any::type_name::<T>(), std::any::type_name::<U>());
+    info!("[{}]: Circuit breaker changed from {:?} to {:?}", service, from, to);
+    if let Some(events) = events {
+        events.send(QueryCircuitEvent { service, from, to });
+    }
+}
+
+/// `T` is the query request content
+/// `U` is the query reply content, served by another App through the `QueryBridgeClient`
+pub fn run_query_bridge_client<T, U>(world: &mut World)
+where
+    T: Send + Sync + 'static + Clone,
+    U: Send + Sync + 'static + Clone,
+{
+    let Some(mut bridge) = world.remove_resource::<QueryBridgeClient<T, U>>() else {
+        return;
+    };
+
+    let mut disconnected = Vec::new();
+    let mut query_queries = world.query_filtered::<(Entity, &mut GoalComponent, &QueryRequest<T>), (With<GoalComponent>, With<QueryRequest<T>>)>();
+    for (entity, mut goal, request) in query_queries.iter_mut(world) {
+        if !goal.accepts_reply::<U>() || !is_goal_pending(&goal) {
+            continue;
+        }
+
+        goal.mark_executing();
+        let uuid = goal.get_uuid();
+        let sent = bridge.sender.send(QueryBridgeRequest {
+            uuid,
+            request: request.request.clone(),
+            reply: bridge.reply_sender.clone(),
         });
+
+        if sent.is_err() {
+            disconnected.push((entity, goal.clone(), request.clone()));
+            continue;
+        }
+
+        debug!("[{:?}]: Request sent over the bridge", uuid);
+        bridge.pending.insert(uuid, (entity, request.clone()));
+    }
+
+    for (entity, goal, request) in disconnected {
+        complete_goal::<T, U>(world, entity, goal, &request, Err(anyhow::anyhow!("Query bridge is disconnected")));
+    }
+
+    let replies: Vec<_> = bridge.reply_receiver.get_mut().map(|receiver| receiver.try_iter().collect()).unwrap_or_default();
+    for (uuid, result) in replies {
+        let Some((entity, request)) = bridge.pending.remove(&uuid) else {
+            continue;
+        };
+
+        let Some(goal) = world.get::<GoalComponent>(entity).cloned() else {
+            warn!("[{:?}]: Request was despawned before completion", uuid);
+            continue;
+        };
+
+        complete_goal::<T, U>(world, entity, goal, &request, result.map_err(anyhow::Error::msg));
+    }
+
+    let abandoned: Vec<_> = bridge
+        .pending
+        .iter()
+        .filter(|(_, (entity, _))| world.get::<GoalComponent>(*entity).is_none_or(|goal| goal.is_cancelled() || goal.is_to_delete()))
+        .map(|(uuid, _)| *uuid)
+        .collect();
+
+    for uuid in abandoned {
+        bridge.pending.remove(&uuid);
+        debug!("[{:?}]: Cancelling the request over the bridge", uuid);
+        let _ = bridge.cancel_sender.send(uuid);
+    }
+
+    world.insert_resource(bridge);
+}
+
+/// Spawns the requests received by the `QueryBridgeServer` and sends back the outcome of their goals
+pub fn run_query_bridge_server<T, U>(world: &mut World)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static + Clone,
+{
+    let Some(mut bridge) = world.remove_resource::<QueryBridgeServer<T, U>>() else {
+        return;
+    };
+
+    let requests: Vec<_> = bridge.receiver.get_mut().map(|receiver| receiver.try_iter().collect()).unwrap_or_default();
+    for request in requests {
+        let uuid = request.uuid;
+        match spawn_request::<T, U>(world, QueryEvent::new(uuid, request.request).with_reply::<U>()) {
+            Some(entity) => {
+                bridge.pending.insert(uuid, (entity, request.reply));
+            }
+            None => {
+                let _ = request.reply.send((uuid, Err("Request was not accepted".to_string())));
+            }
+        }
+    }
+
+    let cancelled: Vec<_> = bridge.cancel_receiver.get_mut().map(|receiver| receiver.try_iter().collect()).unwrap_or_default();
+    for uuid in cancelled {
+        let Some((entity, _)) = bridge.pending.remove(&uuid) else {
+            continue;
+        };
+
+        if let Some(mut goal) = world.get_mut::<GoalComponent>(entity) {
+            if !goal.is_completed() && !goal.is_cancelled() {
+                goal.mark_cancelled();
+                info!("[{:?}]: Goal is cancelled by its bridge client", uuid);
+            }
+        }
+    }
+
+    let finished: Vec<_> = bridge
+        .pending
+        .iter()
+        .filter_map(|(uuid, (entity, _))| get_goal_outcome::<U>(world, *entity).map(|outcome| (*uuid, outcome.cloned())))
+        .collect();
+
+    for (uuid, outcome) in finished {
+        if let Some((_, reply)) = bridge.pending.remove(&uuid) {
+            debug!("[{:?}]: Sending the reply over the bridge", uuid);
+            let _ = reply.send((uuid, outcome));
+        }
     }
+
+    world.insert_resource(bridge);
+}
+
+/// Answers a bridged request whose goal is despawned before `run_query_bridge_server` saw it finish
+pub fn reply_removed_bridge_query<T, U>(trigger: Trigger<OnRemove, GoalComponent>, mut world: DeferredWorld)
+where
+    T: Send + Sync + 'static,
+    U: Send + Sync + 'static + Clone,
+{
+    let entity = trigger.entity();
+    let Some(uuid) = world.get::<GoalComponent>(entity).map(|goal| goal.get_uuid()) else {
+        return;
+    };
+
+    let Some(mut bridge) = world.get_resource_mut::<QueryBridgeServer<T, U>>() else {
+        return;
+    };
+
+    if bridge.pending.get(&uuid).is_none_or(|(pending, _)| *pending != entity) {
+        return;
+    }
+
+    let Some((_, reply)) = bridge.pending.remove(&uuid) else {
+        return;
+    };
+
+    let outcome = get_goal_outcome::<U>(&world, entity).map(|outcome| outcome.cloned());
+    let outcome = outcome.unwrap_or_else(|| Err("Request was despawned before completion".to_string()));
+    let _ = reply.send((uuid, outcome));
 }
@@ -24,8 +24,107 @@ pub trait QueryServerOps<T> {
         Self: Sized;
 }
 
+/// A server handler whose world mutations are reverted when it returns an error
+/// Served by `run_transactional_query_server`
+pub trait QueryTransactionalOps<T> {
+    fn get_reply(transaction: &mut QueryTransaction, request: &QueryRequest<T>) -> Result<Self>
+    where
+        Self: Sized;
+}
+
 pub trait QueryClientOps<T> {
     fn send_request(ctx: &mut bevy_tokio_tasks::TaskContext, request: &QueryRequest<T>) -> impl std::future::Future<Output = Result<Self>> + Send
     where
         Self: Sized;
 }
+
+/// A query whose reply is built from sub-requests
+/// The sub-requests are spawned as children of the goal entity and served by their own servers or clients
+pub trait QueryAggregateOps<T> {
+    /// Called once when the goal starts, sub-requests are spawned with `spawn_child_request`
+    fn spawn_children(world: &mut World, parent: Entity, request: &QueryRequest<T>) -> Result<()>;
+
+    /// Called once every sub-request has completed, `children` is in spawn order
+    fn aggregate(world: &mut World, request: &QueryRequest<T>, children: &[Entity]) -> Result<Self>
+    where
+        Self: Sized;
+}
+
+/// Sends queries straight from `Commands`, without an event
+pub trait QueryCommandsExt {
+    /// Spawns a goal for the service serving `T` with `U`
+    /// Callbacks attached to the returned commands are run by `run_query_callbacks::<U>`
+    fn query<T, U>(&mut self, request: T) -> QueryEntityCommands<'_, U>
+    where
+        T: Send + Sync + 'static,
+        U: Clone + Send + Sync + 'static;
+}
+
+impl QueryCommandsExt for Commands<'_, '_> {
+    fn query<T, U>(&mut self, request: T) -> QueryEntityCommands<'_, U>
+    where
+        T: Send + Sync + 'static,
+        U: Clone + Send + Sync + 'static,
+    {
+        let entity = self.spawn_empty().id();
+        self.queue(move |world: &mut World| {
+            let goal = new_goal::<T, U>(world, uuid::Uuid::new_v4(), &request);
+            info!("[{:?}]: Request spawned", goal.get_uuid());
+            if let Ok(mut entity) = world.get_entity_mut(entity) {
+                entity.insert((goal, QueryRequest { request }));
+            }
+        });
+        QueryEntityCommands::new(self.entity(entity))
+    }
+}
+
+/// Adds query services to an App, recording them in the `QueryServiceRegistry`
+pub trait QueryAppExt {
+    /// Records the service serving `T` with `U` under `name`, for services run by other systems
+    fn register_query_service<T, U>(&mut self, name: impl Into<String>, kind: QueryServiceKind) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+        U: Send + Sync + 'static;
+
+    /// Adds `run_query_server::<T, U>` to `Update` and records it as a server
+    fn add_query_server<T, U>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: Send + Sync + 'static + Clone,
+        U: QueryServerOps<T> + Send + Sync + 'static + Clone;
+
+    /// Adds `run_query_client::<T, U>` to `Update` and records it as a client
+    fn add_query_client<T, U>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: Send + Sync + 'static + Clone,
+        U: QueryClientOps<T> + Send + Sync + 'static + Clone;
+}
+
+impl QueryAppExt for App {
+    fn register_query_service<T, U>(&mut self, name: impl Into<String>, kind: QueryServiceKind) -> &mut Self
+    where
+        T: Send + Sync + 'static,
+        U: Send + Sync + 'static,
+    {
+        self.init_resource::<QueryServiceRegistry>();
+        self.world_mut().resource_mut::<QueryServiceRegistry>().register::<T, U>(name, kind);
+        self
+    }
+
+    fn add_query_server<T, U>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: Send + Sync + 'static + Clone,
+        U: QueryServerOps<T> + Send + Sync + 'static + Clone,
+    {
+        self.register_query_service::<T, U>(name, QueryServiceKind::Server);
+        self.add_systems(Update, run_query_server::<T, U>)
+    }
+
+    fn add_query_client<T, U>(&mut self, name: impl Into<String>) -> &mut Self
+    where
+        T: Send + Sync + 'static + Clone,
+        U: QueryClientOps<T> + Send + Sync + 'static + Clone,
+    {
+        self.register_query_service::<T, U>(name, QueryServiceKind::Client);
+        self.add_systems(Update, run_query_client::<T, U>)
+    }
+}
@@ -0,0 +1,251 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+use super::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use futures_util::{SinkExt, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+
+/// A JSON text message of the WebSocket transport, tagged by `type`
+/// Clients send `request` and `cancel`, the server answers with `feedback` items followed by one `reply`, `error` or `cancelled`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum QueryWebSocketMessage {
+    Request { uuid: uuid::Uuid, service: String, request: serde_json::Value },
+    Cancel { uuid: uuid::Uuid },
+    Feedback { uuid: uuid::Uuid, feedback: serde_json::Value },
+    Reply { uuid: uuid::Uuid, reply: serde_json::Value },
+    Error { uuid: uuid::Uuid, error: String },
+    Cancelled { uuid: uuid::Uuid },
+}
+
+/// The address the WebSocket endpoint is listening on, inserted once the socket is bound
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct QueryWebSocketAddress(pub std::net::SocketAddr);
+
+/// Exposes query services over WebSocket, streaming their feedback until the reply
+/// Requests still in flight when a client disconnects are cancelled
+/// Requires the `TokioTasksPlugin`
+pub struct QueryWebSocketPlugin {
+    address: String,
+    registrations: Vec<RemoteRegistration>,
+}
+
+impl QueryWebSocketPlugin {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`
+    pub fn with_service<T, U>(mut self, name: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register::<T, U>(name.clone());
+        }));
+        self
+    }
+
+    /// Exposes the service serving `T` with `U` under `name`, streaming the items of its `QueryFeedback<F>`
+    pub fn with_feedback_service<T, U, F>(mut self, name: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + QueryRemoteContent + Send + Sync + 'static,
+        U: Serialize + QueryRemoteContent + Send + Sync + 'static,
+        F: Serialize + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.registrations.push(Box::new(move |services| {
+            services.register_with_feedback::<T, U, F>(name.clone());
+        }));
+        self
+    }
+}
+
+impl Plugin for QueryWebSocketPlugin {
+    fn build(&self, app: &mut App) {
+        let transport = add_remote_services(app, &self.registrations);
+
+        let address = self.address.clone();
+        app.add_systems(Startup, move |runtime: Res<TokioTasksRuntime>| {
+            let address = address.clone();
+            runtime.spawn_background_task(move |ctx| run_websocket_server(ctx, transport, address));
+        });
+    }
+}
+
+async fn run_websocket_server(ctx: TaskContext, transport: QueryRemoteTransport, address: String) {
+    serve_tcp_connections(ctx, "WebSocket endpoint", address, QueryWebSocketAddress, move |ctx, stream| {
+        handle_websocket_connection(ctx, transport, stream)
+    })
+    .await;
+}
+
+/// Requests of one connection still waiting for their reply, `true` once cancelled
+type InFlightRequests = Arc<Mutex<HashMap<uuid::Uuid, bool>>>;
+
+async fn handle_websocket_connection(mut ctx: TaskContext, transport: QueryRemoteTransport, stream: tokio::net::TcpStream) {
+    let socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("WebSocket handshake failed: {}", e);
+            return;
+        }
+    };
+
+    let (mut writer, mut reader) = socket.split();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<QueryWebSocketMessage>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            let text = match serde_json::to_string(&message) {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("Failed to encode a WebSocket message: {}", e);
+                    continue;
+                }
+            };
+
+            if let Err(e) = writer.send(Message::text(text)).await {
+                warn!("WebSocket endpoint failed to write a message: {}", e);
+                break;
+            }
+        }
+    });
+
+    let in_flight = InFlightRequests::default();
+    while let Some(message) = reader.next().await {
+        let text = match message {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => break,
+            Ok(_) => continue,
+            Err(e) => {
+                warn!("WebSocket endpoint failed to read a message: {}", e);
+                break;
+            }
+        };
+
+        match serde_json::from_str::<QueryWebSocketMessage>(text.as_str()) {
+            Ok(QueryWebSocketMessage::Request { uuid, service, request }) => {
+                if in_flight.lock().unwrap().insert(uuid, false).is_some() {
+                    let _ = sender.send(QueryWebSocketMessage::Error {
+                        uuid,
+                        error: format!("Request {} is already in flight", uuid),
+                    });
+                    continue;
+                }
+
+                let request = RemoteQueryRequest { uuid, service, request };
+                tokio::spawn(serve_websocket_request(ctx.clone(), transport, request, sender.clone(), in_flight.clone()));
+            }
+            Ok(QueryWebSocketMessage::Cancel { uuid }) => {
+                if let Err(e) = cancel_websocket_request(&mut ctx, &in_flight, uuid).await {
+                    let _ = sender.send(QueryWebSocketMessage::Error { uuid, error: e.to_string() });
+                }
+            }
+            Ok(_) => {
+                let _ = sender.send(QueryWebSocketMessage::Error {
+                    uuid: uuid::Uuid::nil(),
+                    error: "Only request and cancel messages are accepted".to_string(),
+                });
+            }
+            Err(e) => {
+                let _ = sender.send(QueryWebSocketMessage::Error {
+                    uuid: uuid::Uuid::nil(),
+                    error: format!("Invalid message: {}", e),
+                });
+            }
+        }
+    }
+
+    let abandoned: Vec<_> = in_flight.lock().unwrap().iter().filter(|(_, is_cancelled)| !**is_cancelled).map(|(uuid, _)| *uuid).collect();
+    if !abandoned.is_empty() {
+        ctx.run_on_main_thread(move |ctx| {
+            for uuid in abandoned {
+                cancel_remote_query(ctx.world, uuid);
+            }
+        })
+        .await;
+    }
+
+    drop(sender);
+    let _ = writer_task.await;
+}
+
+/// Cancels a request of this connection only
+/// The request is marked as cancelled before its goal is, so a reply racing the cancel is still reported as cancelled
+async fn cancel_websocket_request(ctx: &mut TaskContext, in_flight: &InFlightRequests, uuid: uuid::Uuid) -> Result<(), &'static str> {
+    match in_flight.lock().unwrap().get_mut(&uuid) {
+        Some(is_cancelled) if *is_cancelled => return Err("Request is already cancelled"),
+        Some(is_cancelled) => *is_cancelled = true,
+        None => return Err("No pending request to cancel"),
+    }
+
+    if ctx.run_on_main_thread(move |ctx| cancel_remote_query(ctx.world, uuid)).await {
+        return Ok(());
+    }
+
+    // The request may have finished meanwhile, and is then no longer in flight
+    if let Some(is_cancelled) = in_flight.lock().unwrap().get_mut(&uuid) {
+        *is_cancelled = false;
+    }
+    Err("No pending request to cancel")
+}
+
+async fn serve_websocket_request(
+    mut ctx: TaskContext,
+    transport: QueryRemoteTransport,
+    request: RemoteQueryRequest,
+    sender: tokio::sync::mpsc::UnboundedSender<QueryWebSocketMessage>,
+    in_flight: InFlightRequests,
+) {
+    let uuid = request.uuid;
+    let (feedback_sender, mut feedback_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let submitted = submit_remote_query_with_feedback(&mut ctx, transport, request, feedback_sender);
+    tokio::pin!(submitted);
+
+    let response = loop {
+        tokio::select! {
+            response = &mut submitted => break response,
+            Some(feedback) = feedback_receiver.recv() => {
+                let _ = sender.send(QueryWebSocketMessage::Feedback { uuid, feedback });
+            }
+        }
+    };
+
+    while let Ok(feedback) = feedback_receiver.try_recv() {
+        let _ = sender.send(QueryWebSocketMessage::Feedback { uuid, feedback });
+    }
+
+    let is_cancelled = in_flight.lock().unwrap().remove(&uuid).unwrap_or(false);
+    let message = match (response.reply, response.error) {
+        (Some(reply), _) => QueryWebSocketMessage::Reply { uuid, reply },
+        (None, _) if is_cancelled => QueryWebSocketMessage::Cancelled { uuid },
+        (None, error) => QueryWebSocketMessage::Error {
+            uuid,
+            error: error.unwrap_or_else(|| "Query failed".to_string()),
+        },
+    };
+    let _ = sender.send(message);
+}
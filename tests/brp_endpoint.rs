@@ -0,0 +1,115 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+mod common;
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+use bevy_reflect::Reflect;
+use bevy_remote::http::RemoteHttpPlugin;
+use bevy_remote::RemotePlugin;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Clone, Reflect)]
+struct Request {
+    count: i32,
+}
+
+#[derive(Clone, Reflect)]
+struct Reply {
+    count: i32,
+}
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        if request.request.count < 0 {
+            anyhow::bail!("Negative request");
+        }
+        Ok(Reply { count: request.request.count * 2 })
+    }
+}
+
+fn new_app() -> (App, std::net::SocketAddr) {
+    bevy_tasks::IoTaskPool::get_or_init(bevy_tasks::TaskPool::new);
+    let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let mut app = App::new();
+    app.add_plugins(RemotePlugin::default().with_query_service::<Request, Reply>("double"));
+    app.add_plugins(RemoteHttpPlugin::default().with_port(address.port()));
+    app.add_plugins(QueryBrpPlugin);
+    app.add_systems(Update, (run_query_server::<Request, Reply>, cleanup_requests).chain());
+    app.update();
+    (app, address)
+}
+
+/// Posts a JSON-RPC call while updating the app, returning the JSON response
+fn post(app: &mut App, address: std::net::SocketAddr, call: serde_json::Value) -> serde_json::Value {
+    let body = call.to_string();
+    let request = format!(
+        "POST / HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        address,
+        body.len(),
+        body
+    );
+    let response = common::run_client(app, move || async move {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    });
+
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn serves_query_methods_on_the_bevy_remote_endpoint() {
+    let (mut app, address) = new_app();
+    let response = post(&mut app, address, serde_json::json!({ "jsonrpc": "2.0", "method": "query/double", "params": { "count": 21 }, "id": 1 }));
+    assert_eq!(response, serde_json::json!({ "jsonrpc": "2.0", "result": { "count": 42 }, "id": 1 }));
+
+    // The answered call is forgotten once bevy_remote stops polling it
+    for _ in 0..3 {
+        app.update();
+    }
+    assert!(app.world().resource::<QueryBrpCalls>().is_empty());
+}
+
+#[test]
+fn answers_failures_with_their_reason() {
+    let (mut app, address) = new_app();
+    let response = post(&mut app, address, serde_json::json!({ "jsonrpc": "2.0", "method": "query/double", "params": { "count": -1 }, "id": 1 }));
+    assert_eq!(response["error"]["code"], QUERY_BRP_FAILED);
+    assert_eq!(response["error"]["message"], "Negative request");
+}
+
+#[test]
+fn refuses_params_of_another_type() {
+    let (mut app, address) = new_app();
+    let response = post(&mut app, address, serde_json::json!({ "jsonrpc": "2.0", "method": "query/double", "params": "twenty", "id": 1 }));
+    assert_eq!(response["error"]["code"], -32602);
+}
+
+#[test]
+fn keeps_the_builtin_methods() {
+    let (mut app, address) = new_app();
+    let response = post(&mut app, address, serde_json::json!({ "jsonrpc": "2.0", "method": "bevy/list", "id": 1 }));
+    assert!(response["result"].is_array());
+}
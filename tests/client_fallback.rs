@@ -0,0 +1,84 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+
+#[derive(Clone)]
+struct Request;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Reply(&'static str);
+
+impl QueryClientOps<Request> for Reply {
+    async fn send_request(_ctx: &mut bevy_tokio_tasks::TaskContext, _request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        anyhow::bail!("Primary failed");
+    }
+}
+
+/// How many times the fallback handler ran
+#[derive(Resource, Default)]
+struct FallbackCalls(u32);
+
+fn reply_from_fallback(world: &mut World, _request: &QueryRequest<Request>) -> anyhow::Result<Reply> {
+    world.resource_mut::<FallbackCalls>().0 += 1;
+    Ok(Reply("fallback"))
+}
+
+/// Spawns one request whose client fails, letting the client pick it up
+fn new_app() -> (App, Entity) {
+    let mut app = App::new();
+    app.add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default());
+    app.init_resource::<FallbackCalls>();
+    app.insert_resource(QueryFallback::<Request, Reply>::handler(reply_from_fallback));
+    app.add_systems(Update, run_query_client::<Request, Reply>);
+    app.update();
+
+    let entity = spawn_request::<Request, Reply>(app.world_mut(), QueryEvent::new(uuid::Uuid::new_v4(), Request)).unwrap();
+    app.update();
+    (app, entity)
+}
+
+/// Updates the app long enough for the client to fail
+fn wait_for_client(app: &mut App) {
+    for _ in 0..20 {
+        app.update();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn replies_with_the_fallback_when_the_client_fails() {
+    let (mut app, entity) = new_app();
+    wait_for_client(&mut app);
+    assert!(app.world().get::<GoalComponent>(entity).unwrap().is_completed());
+    assert_eq!(app.world().get::<QueryReply<Reply>>(entity).unwrap().reply, Reply("fallback"));
+    assert_eq!(app.world().resource::<FallbackCalls>().0, 1);
+}
+
+#[test]
+fn skips_the_fallback_of_cancelled_goals() {
+    let (mut app, entity) = new_app();
+    app.world_mut().get_mut::<GoalComponent>(entity).unwrap().mark_cancelled();
+    wait_for_client(&mut app);
+    assert!(app.world().get::<GoalComponent>(entity).unwrap().is_cancelled());
+    assert!(app.world().get::<QueryReply<Reply>>(entity).is_none());
+    assert_eq!(app.world().resource::<FallbackCalls>().0, 0);
+}
@@ -0,0 +1,92 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+const HEDGE_DELAY: std::time::Duration = std::time::Duration::from_millis(20);
+
+/// The first attempt fails after the hedged request is sent, the second succeeds later unless `is_second_failing`
+#[derive(Clone)]
+struct Request {
+    attempts: Arc<AtomicU32>,
+    is_second_failing: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Reply(u32);
+
+impl QueryClientOps<Request> for Reply {
+    async fn send_request(_ctx: &mut bevy_tokio_tasks::TaskContext, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        let attempt = request.request.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+        if attempt == 1 {
+            tokio::time::sleep(HEDGE_DELAY * 2).await;
+            anyhow::bail!("Attempt 1 failed");
+        }
+
+        tokio::time::sleep(HEDGE_DELAY * 4).await;
+        if request.request.is_second_failing {
+            anyhow::bail!("Attempt 2 failed");
+        }
+        Ok(Reply(attempt))
+    }
+}
+
+/// Sends one hedged request and updates the app until its goal has an outcome
+fn run_hedged_request(is_second_failing: bool) -> (App, Entity) {
+    let mut app = App::new();
+    app.add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default());
+    app.insert_resource(QueryHedge::<Request, Reply>::new(HEDGE_DELAY));
+    app.add_systems(Update, run_query_client::<Request, Reply>);
+    app.update();
+
+    let request = Request {
+        attempts: Arc::new(AtomicU32::new(0)),
+        is_second_failing,
+    };
+    let entity = spawn_request::<Request, Reply>(app.world_mut(), QueryEvent::new(uuid::Uuid::new_v4(), request)).unwrap();
+    for _ in 0..500 {
+        app.update();
+        let goal = app.world().get::<GoalComponent>(entity).unwrap();
+        if goal.is_completed() || goal.is_failed() {
+            return (app, entity);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("Hedged request did not finish");
+}
+
+#[test]
+fn the_hedged_request_recovers_a_failed_first_attempt() {
+    let (app, entity) = run_hedged_request(false);
+    let goal = app.world().get::<GoalComponent>(entity).unwrap();
+    assert!(goal.is_completed());
+    assert_eq!(goal.get_winning_attempt(), Some(2));
+    assert_eq!(app.world().get::<QueryReply<Reply>>(entity).unwrap().reply, Reply(2));
+}
+
+#[test]
+fn fails_only_when_both_attempts_fail() {
+    let (app, entity) = run_hedged_request(true);
+    let goal = app.world().get::<GoalComponent>(entity).unwrap();
+    assert!(goal.is_failed());
+    assert_eq!(goal.get_reason(), Some("Attempt 2 failed"));
+}
@@ -0,0 +1,55 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+#![allow(dead_code)]
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+
+/// Adds the tokio runtime and the plugins of `build` to a new app, updating it until its server inserts the address resource `A`
+pub fn new_app<A: Resource>(build: impl FnOnce(&mut App), get_address: impl Fn(&A) -> std::net::SocketAddr) -> (App, std::net::SocketAddr) {
+    let mut app = App::new();
+    app.add_plugins(bevy_tokio_tasks::TokioTasksPlugin::default());
+    build(&mut app);
+
+    for _ in 0..500 {
+        app.update();
+        if let Some(address) = app.world().get_resource::<A>() {
+            let address = get_address(address);
+            return (app, address);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("Server did not start");
+}
+
+/// Updates the app until the client, running on its own runtime, is done
+pub fn run_client<R, F>(app: &mut App, client: impl FnOnce() -> F + Send + 'static) -> R
+where
+    R: Send + 'static,
+    F: std::future::Future<Output = R>,
+{
+    let client = std::thread::spawn(move || tokio::runtime::Runtime::new().unwrap().block_on(client()));
+    for _ in 0..500 {
+        if client.is_finished() {
+            return client.join().unwrap();
+        }
+        app.update();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    panic!("Client did not finish");
+}
@@ -0,0 +1,111 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+mod common;
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Request(i32);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Reply(i32);
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        if request.request.0 < 0 {
+            anyhow::bail!("Negative request");
+        }
+        Ok(Reply(request.request.0 * 2))
+    }
+}
+
+/// Served only by the TCP server, to check the gateway keeps to its own services
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Hidden(i32);
+
+impl QueryServerOps<Hidden> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Hidden>) -> anyhow::Result<Self> {
+        Ok(Reply(request.request.0))
+    }
+}
+
+fn new_app() -> (App, std::net::SocketAddr) {
+    common::new_app(
+        |app| {
+            app.add_plugins(QueryHttpGatewayPlugin::new("127.0.0.1:0").with_service::<Request, Reply>("double"));
+            app.add_plugins(QueryRemoteServerPlugin::new("127.0.0.1:0").with_service::<Hidden, Reply>("hidden"));
+            app.add_systems(Update, (run_query_server::<Request, Reply>, run_query_server::<Hidden, Reply>, cleanup_requests).chain());
+        },
+        |address: &QueryHttpGatewayAddress| address.0,
+    )
+}
+
+/// Posts `body` to `path` while updating the app, returning the status and the JSON body
+fn post(app: &mut App, address: std::net::SocketAddr, path: &str, body: &str) -> (u16, serde_json::Value) {
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        address,
+        body.len(),
+        body
+    );
+    let response = common::run_client(app, move || async move {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    });
+
+    let status = response.split_whitespace().nth(1).and_then(|status| status.parse().ok()).unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[test]
+fn replies_over_http() {
+    let (mut app, address) = new_app();
+    assert_eq!(post(&mut app, address, "/query/double", "21"), (200, serde_json::json!(42)));
+}
+
+#[test]
+fn answers_failures_with_their_reason() {
+    let (mut app, address) = new_app();
+    assert_eq!(post(&mut app, address, "/query/double", "-1"), (500, serde_json::json!({ "error": "Negative request" })));
+}
+
+#[test]
+fn refuses_invalid_bodies() {
+    let (mut app, address) = new_app();
+    let (status, _) = post(&mut app, address, "/query/double", "{");
+    assert_eq!(status, 400);
+}
+
+#[test]
+fn only_serves_its_own_services() {
+    let (mut app, address) = new_app();
+    assert_eq!(post(&mut app, address, "/query/hidden", "1"), (404, serde_json::json!({ "error": "Unknown service: hidden" })));
+}
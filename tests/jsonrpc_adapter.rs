@@ -0,0 +1,103 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+mod common;
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Request(i32);
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Reply(i32);
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        Ok(Reply(request.request.0 * 2))
+    }
+}
+
+/// A request without a server system, whose goals stay in flight
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Stalled;
+
+fn new_app() -> (App, std::net::SocketAddr) {
+    common::new_app(
+        |app| {
+            app.add_plugins(QueryJsonRpcPlugin::new("127.0.0.1:0").with_method::<Request, Reply>("double").with_method::<Stalled, Reply>("stalled"));
+            app.add_systems(Update, (run_query_server::<Request, Reply>, cleanup_requests).chain());
+        },
+        |address: &QueryJsonRpcAddress| address.0,
+    )
+}
+
+/// Writes `calls` on one connection, then reads `count` responses while updating the app
+fn call(app: &mut App, address: std::net::SocketAddr, calls: Vec<serde_json::Value>, count: usize) -> Vec<serde_json::Value> {
+    let (_writer, responses) = common::run_client(app, move || async move {
+        let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        for call in calls {
+            writer.write_all(format!("{}\n", call).as_bytes()).await.unwrap();
+        }
+
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+        let mut responses = Vec::new();
+        while responses.len() < count {
+            let line = lines.next_line().await.unwrap().unwrap();
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+        (writer, responses)
+    });
+    responses
+}
+
+#[test]
+fn answers_calls_and_forgets_them() {
+    let (mut app, address) = new_app();
+    let responses = call(&mut app, address, vec![serde_json::json!({ "jsonrpc": "2.0", "method": "double", "params": 21, "id": 7 })], 1);
+    assert_eq!(responses, vec![serde_json::json!({ "jsonrpc": "2.0", "result": 42, "id": 7 })]);
+    assert!(app.world().resource::<QueryJsonRpcCalls>().is_empty());
+}
+
+#[test]
+fn refuses_an_id_already_in_flight() {
+    let (mut app, address) = new_app();
+    let stalled = serde_json::json!({ "jsonrpc": "2.0", "method": "stalled", "params": null, "id": 1 });
+    let responses = call(&mut app, address, vec![stalled.clone(), stalled], 1);
+    assert_eq!(responses[0]["id"], 1);
+    assert_eq!(responses[0]["error"]["code"], QueryJsonRpcError::INVALID_REQUEST);
+    assert_eq!(responses[0]["error"]["message"], "Call 1 is already in flight");
+
+    // The first call is still served by its goal
+    for _ in 0..100 {
+        if app.world().get_resource::<QueryRemotePending>().is_some_and(|pending| pending.len() == 1) {
+            break;
+        }
+        app.update();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(app.world().resource::<QueryRemotePending>().len(), 1);
+    assert_eq!(app.world().resource::<QueryJsonRpcCalls>().len(), 1);
+}
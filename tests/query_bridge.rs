@@ -0,0 +1,113 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+
+#[derive(Clone)]
+struct Request(&'static str);
+
+#[derive(Clone, Debug, PartialEq)]
+struct Reply(&'static str);
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        Ok(Reply(request.request.0))
+    }
+}
+
+/// Bridges two Apps, the server App only replying when `is_serving`
+fn new_apps(is_serving: bool) -> (App, App) {
+    let (client, server) = query_bridge::<Request, Reply>();
+
+    let mut client_app = App::new();
+    client_app.add_plugins(client);
+
+    let mut server_app = App::new();
+    server_app.add_plugins(server);
+    if is_serving {
+        server_app.add_systems(Update, run_query_server::<Request, Reply>);
+    }
+
+    (client_app, server_app)
+}
+
+/// Sends one request from the client App and lets it cross the bridge
+fn send_request(client_app: &mut App, server_app: &mut App) -> Entity {
+    let entity = spawn_request::<Request, Reply>(client_app.world_mut(), QueryEvent::new(uuid::Uuid::new_v4(), Request("ping"))).unwrap();
+    exchange(client_app, server_app);
+    entity
+}
+
+/// Updates both Apps long enough for a message to cross the bridge and be answered
+fn exchange(client_app: &mut App, server_app: &mut App) {
+    for _ in 0..3 {
+        client_app.update();
+        server_app.update();
+    }
+    client_app.update();
+}
+
+/// The goal spawned in the server App for the request of the client goal
+fn get_server_goal(client_app: &App, server_app: &mut App, entity: Entity) -> Option<Entity> {
+    let uuid = client_app.world().get::<GoalComponent>(entity).unwrap().get_uuid();
+    let mut goals = server_app.world_mut().query::<(Entity, &GoalComponent)>();
+    goals.iter(server_app.world()).find(|(_, goal)| goal.get_uuid() == uuid).map(|(entity, _)| entity)
+}
+
+#[test]
+fn completes_the_goal_with_the_reply_of_the_other_app() {
+    let (mut client_app, mut server_app) = new_apps(true);
+    let entity = send_request(&mut client_app, &mut server_app);
+
+    assert!(client_app.world().get::<GoalComponent>(entity).unwrap().is_completed());
+    assert_eq!(client_app.world().get::<QueryReply<Reply>>(entity).unwrap().reply, Reply("ping"));
+    assert_eq!(client_app.world().resource::<QueryBridgeClient<Request, Reply>>().get_pending_count(), 0);
+    assert_eq!(server_app.world().resource::<QueryBridgeServer<Request, Reply>>().get_pending_count(), 0);
+}
+
+#[test]
+fn cancels_the_goal_of_the_other_app() {
+    let (mut client_app, mut server_app) = new_apps(false);
+    let entity = send_request(&mut client_app, &mut server_app);
+    let server_goal = get_server_goal(&client_app, &mut server_app, entity).unwrap();
+    assert!(!server_app.world().get::<GoalComponent>(server_goal).unwrap().is_cancelled());
+
+    client_app.world_mut().get_mut::<GoalComponent>(entity).unwrap().mark_cancelled();
+    exchange(&mut client_app, &mut server_app);
+
+    assert!(server_app.world().get::<GoalComponent>(server_goal).unwrap().is_cancelled());
+    assert_eq!(client_app.world().resource::<QueryBridgeClient<Request, Reply>>().get_pending_count(), 0);
+    assert_eq!(server_app.world().resource::<QueryBridgeServer<Request, Reply>>().get_pending_count(), 0);
+}
+
+#[test]
+fn fails_the_goal_despawned_in_the_other_app() {
+    let (mut client_app, mut server_app) = new_apps(false);
+    let entity = send_request(&mut client_app, &mut server_app);
+    let server_goal = get_server_goal(&client_app, &mut server_app, entity).unwrap();
+
+    server_app.world_mut().despawn(server_goal);
+    client_app.update();
+
+    let goal = client_app.world().get::<GoalComponent>(entity).unwrap();
+    assert!(goal.is_failed());
+    assert_eq!(goal.get_reason(), Some("Request was despawned before completion"));
+    assert!(client_app.world().get::<QueryReply<Reply>>(entity).is_none());
+}
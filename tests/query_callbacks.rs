@@ -0,0 +1,110 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::RunSystemOnce;
+use bevy_query_service::*;
+
+#[derive(Clone)]
+struct Request;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Reply(&'static str);
+
+/// How many times each callback ran
+#[derive(Resource, Default)]
+struct Calls {
+    replies: u32,
+    errors: u32,
+}
+
+/// Spawns one goal from `Commands` with both callbacks, optionally despawning it from its reply callback
+fn new_app(despawn_on_reply: bool) -> (App, Entity, QueryCallbacks<Reply>) {
+    let mut app = App::new();
+    app.init_resource::<Calls>();
+    app.add_systems(Update, run_query_callbacks::<Reply>);
+
+    let entity = app
+        .world_mut()
+        .run_system_once(move |mut commands: Commands| {
+            let query = commands.query::<Request, Reply>(Request);
+            let entity = query.id();
+            query
+                .on_reply(move |_reply, commands| {
+                    commands.queue(|world: &mut World| world.resource_mut::<Calls>().replies += 1);
+                    if despawn_on_reply {
+                        commands.entity(entity).despawn();
+                    }
+                })
+                .on_error(|_reason, commands| {
+                    commands.queue(|world: &mut World| world.resource_mut::<Calls>().errors += 1);
+                });
+            entity
+        })
+        .unwrap();
+
+    let callbacks = app.world().get::<QueryCallbacks<Reply>>(entity).unwrap().clone();
+    (app, entity, callbacks)
+}
+
+/// Whether any system of the callbacks is still registered
+fn is_registered(app: &App, callbacks: &QueryCallbacks<Reply>) -> bool {
+    let systems = [callbacks.get_on_reply().unwrap().entity(), callbacks.get_on_error().unwrap().entity()];
+    systems.into_iter().any(|system| app.world().get_entity(system).is_ok())
+}
+
+/// Completes the goal with a reply
+fn complete(app: &mut App, entity: Entity) {
+    let mut goal = app.world_mut().entity_mut(entity);
+    goal.insert(QueryReply { reply: Reply("done") });
+    goal.get_mut::<GoalComponent>().unwrap().mark_completed();
+}
+
+#[test]
+fn unregisters_the_callbacks_once_run() {
+    let (mut app, entity, callbacks) = new_app(false);
+    complete(&mut app, entity);
+    app.update();
+    assert_eq!(app.world().resource::<Calls>().replies, 1);
+    assert_eq!(app.world().resource::<Calls>().errors, 0);
+    assert!(app.world().get::<QueryCallbacks<Reply>>(entity).is_none());
+    assert!(!is_registered(&app, &callbacks));
+}
+
+#[test]
+fn unregisters_the_callbacks_of_despawned_goals() {
+    let (mut app, entity, callbacks) = new_app(false);
+    assert!(is_registered(&app, &callbacks));
+    app.world_mut().despawn(entity);
+    app.world_mut().flush();
+    assert!(!is_registered(&app, &callbacks));
+    app.update();
+    assert_eq!(app.world().resource::<Calls>().replies, 0);
+    assert_eq!(app.world().resource::<Calls>().errors, 0);
+}
+
+#[test]
+fn unregisters_a_callback_despawning_its_goal() {
+    let (mut app, entity, callbacks) = new_app(true);
+    complete(&mut app, entity);
+    app.update();
+    assert_eq!(app.world().resource::<Calls>().replies, 1);
+    assert!(app.world().get_entity(entity).is_err());
+    assert!(!is_registered(&app, &callbacks));
+}
@@ -0,0 +1,276 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+mod common;
+
+use bevy_app::prelude::*;
+use bevy_ecs::prelude::*;
+use bevy_query_service::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Request(i32);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Reply(i32);
+
+impl QueryServerOps<Request> for Reply {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Request>) -> anyhow::Result<Self> {
+        if request.request.0 < 0 {
+            anyhow::bail!("Negative request");
+        }
+        Ok(Reply(request.request.0 * 2))
+    }
+}
+
+/// A request without a server system, whose goals stay in flight
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Stalled;
+
+/// Tuple keys, which JSON cannot encode but binary codecs can
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Cells(std::collections::HashMap<(i32, i32), i32>);
+
+impl QueryServerOps<Cells> for Cells {
+    fn get_reply(_world: &mut World, request: &QueryRequest<Cells>) -> anyhow::Result<Self> {
+        Ok(Cells(request.request.0.iter().map(|((x, y), value)| ((*y, *x), *value)).collect()))
+    }
+}
+
+/// JSON under a name no server registers
+struct UnknownCodec;
+
+impl QueryCodec for UnknownCodec {
+    const NAME: &'static str = "unknown";
+
+    fn encode<V: Serialize>(value: &V) -> anyhow::Result<Vec<u8>> {
+        QueryJsonCodec::encode(value)
+    }
+
+    fn decode<V: serde::de::DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<V> {
+        QueryJsonCodec::decode(bytes)
+    }
+}
+
+fn new_app() -> (App, std::net::SocketAddr) {
+    common::new_app(
+        |app| {
+            let plugin = QueryRemoteServerPlugin::new("127.0.0.1:0")
+                .with_service::<Request, Reply>("double")
+                .with_service::<Stalled, Reply>("stalled")
+                .with_service::<Cells, Cells>("transpose");
+            #[cfg(feature = "msgpack")]
+            let plugin = plugin.with_service_codec::<Cells, Cells, QueryMessagePackCodec>("transpose");
+            #[cfg(feature = "bincode")]
+            let plugin = plugin.with_service_codec::<Cells, Cells, QueryBincodeCodec>("transpose");
+            #[cfg(feature = "cbor")]
+            let plugin = plugin.with_service_codec::<Cells, Cells, QueryCborCodec>("transpose");
+            app.add_plugins(plugin);
+            app.add_systems(Update, (run_query_server::<Request, Reply>, run_query_server::<Cells, Cells>, cleanup_requests).chain());
+        },
+        |address: &QueryRemoteServerAddress| address.0,
+    )
+}
+
+async fn send_frame(stream: &mut tokio::net::TcpStream, request: &RemoteQueryRequest) -> RemoteQueryResponse {
+    write_frame(stream, &serde_json::to_vec(request).unwrap()).await.unwrap();
+    let frame = read_frame(stream).await.unwrap().unwrap();
+    serde_json::from_slice(&frame).unwrap()
+}
+
+async fn send_handshake(stream: &mut tokio::net::TcpStream, codecs: &[&str]) -> Vec<String> {
+    let handshake = RemoteQueryHandshake {
+        codecs: codecs.iter().map(|codec| codec.to_string()).collect(),
+    };
+    write_frame(stream, &serde_json::to_vec(&handshake).unwrap()).await.unwrap();
+    let frame = read_frame(stream).await.unwrap().unwrap();
+    serde_json::from_slice::<RemoteQueryHandshake>(&frame).unwrap().codecs
+}
+
+/// Negotiates `C` over a raw handshake, then round-trips tuple-keyed cells with it through the client
+#[cfg(any(feature = "msgpack", feature = "bincode", feature = "cbor"))]
+fn round_trip_cells<C: QueryCodec>() {
+    let (mut app, address) = new_app();
+    let cells = Cells([((1, 2), 3), ((4, 5), 6)].into_iter().collect());
+    let (picked, reply) = common::run_client(&mut app, move || async move {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let picked = send_handshake(&mut stream, &["unknown", C::NAME]).await;
+        let reply = QueryRemoteClient::new(address).query_with::<C, Cells, Cells>("transpose", &cells).await;
+        (picked, reply)
+    });
+    assert_eq!(picked, vec![C::NAME.to_string()]);
+    assert_eq!(reply.unwrap(), Cells([((2, 1), 3), ((5, 4), 6)].into_iter().collect()));
+}
+
+#[test]
+#[cfg(feature = "msgpack")]
+fn round_trips_over_msgpack() {
+    round_trip_cells::<QueryMessagePackCodec>();
+}
+
+#[test]
+#[cfg(feature = "bincode")]
+fn round_trips_over_bincode() {
+    round_trip_cells::<QueryBincodeCodec>();
+}
+
+#[test]
+#[cfg(feature = "cbor")]
+fn round_trips_over_cbor() {
+    round_trip_cells::<QueryCborCodec>();
+}
+
+#[test]
+#[cfg(feature = "bincode")]
+fn refuses_a_codec_the_service_does_not_accept() {
+    let (mut app, address) = new_app();
+    let reply = common::run_client(&mut app, move || async move { QueryRemoteClient::new(address).query_with::<QueryBincodeCodec, Request, Reply>("double", &Request(1)).await });
+    assert_eq!(reply.unwrap_err().to_string(), "Service double does not accept the bincode codec");
+}
+
+#[test]
+fn refuses_an_unsupported_codec() {
+    let (mut app, address) = new_app();
+    let (picked, reply) = common::run_client(&mut app, move || async move {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        let picked = send_handshake(&mut stream, &["unknown"]).await;
+        let reply = QueryRemoteClient::new(address).query_with::<UnknownCodec, Cells, Cells>("transpose", &Cells::default()).await;
+        (picked, reply)
+    });
+    assert!(picked.is_empty());
+    assert_eq!(reply.unwrap_err().to_string(), format!("Query server on {} does not support the unknown codec", address));
+}
+
+/// Doubles the `i32` of each JSON request, closing each connection after `per_connection` requests
+/// Returns its address and the number of connections it accepted
+async fn run_closing_server(per_connection: usize) -> (std::net::SocketAddr, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let accepted = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            tokio::spawn(async move {
+                for _ in 0..per_connection {
+                    let Some(frame) = read_frame(&mut stream).await.unwrap() else {
+                        return;
+                    };
+                    let request = serde_json::from_slice::<RemoteQueryRequest>(&frame).unwrap();
+                    let reply = serde_json::json!(request.request.as_i64().unwrap() * 2);
+                    let response = RemoteQueryResponse::success(request.uuid, reply);
+                    write_frame(&mut stream, &serde_json::to_vec(&response).unwrap()).await.unwrap();
+                }
+            });
+        }
+    });
+    (address, accepted)
+}
+
+#[tokio::test]
+async fn reuses_a_pooled_connection() {
+    let (address, accepted) = run_closing_server(usize::MAX).await;
+    let client = QueryRemoteClient::new(address);
+    assert_eq!(client.query::<Request, Reply>("double", &Request(1)).await.unwrap(), Reply(2));
+    assert_eq!(client.get_idle_count(), 1);
+    assert_eq!(client.query::<Request, Reply>("double", &Request(2)).await.unwrap(), Reply(4));
+    assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn reconnects_when_the_server_closed_an_idle_connection() {
+    let (address, accepted) = run_closing_server(1).await;
+    let client = QueryRemoteClient::new(address);
+    assert_eq!(client.query::<Request, Reply>("double", &Request(1)).await.unwrap(), Reply(2));
+    assert_eq!(client.get_idle_count(), 1);
+
+    // Lets the server close the pooled connection
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(client.query::<Request, Reply>("double", &Request(2)).await.unwrap(), Reply(4));
+    assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+#[test]
+fn replies_to_a_loopback_client() {
+    let (mut app, address) = new_app();
+    let reply = common::run_client(&mut app, move || async move { QueryRemoteClient::new(address).query::<Request, Reply>("double", &Request(21)).await });
+    assert_eq!(reply.unwrap(), Reply(21 * 2));
+}
+
+#[test]
+fn answers_with_the_failure_reason() {
+    let (mut app, address) = new_app();
+    let reply = common::run_client(&mut app, move || async move { QueryRemoteClient::new(address).query::<Request, Reply>("double", &Request(-1)).await });
+    assert_eq!(reply.unwrap_err().to_string(), "Negative request");
+}
+
+#[test]
+fn refuses_unknown_services() {
+    let (mut app, address) = new_app();
+    let reply = common::run_client(&mut app, move || async move { QueryRemoteClient::new(address).query::<Request, Reply>("triple", &Request(1)).await });
+    assert_eq!(reply.unwrap_err().to_string(), "Unknown service: triple");
+}
+
+#[test]
+fn refuses_a_uuid_already_in_flight() {
+    let (mut app, address) = new_app();
+    let uuid = uuid::Uuid::new_v4();
+    let (go_sender, go_receiver) = std::sync::mpsc::channel();
+    let client = std::thread::spawn(move || {
+        tokio::runtime::Runtime::new().unwrap().block_on(async move {
+            let request = RemoteQueryRequest {
+                uuid,
+                service: "stalled".to_string(),
+                request: serde_json::json!(null),
+            };
+
+            let mut first = tokio::net::TcpStream::connect(address).await.unwrap();
+            write_frame(&mut first, &serde_json::to_vec(&request).unwrap()).await.unwrap();
+
+            // Another connection reuses the uuid once the first goal is pending
+            go_receiver.recv().unwrap();
+            let mut second = tokio::net::TcpStream::connect(address).await.unwrap();
+            let response = send_frame(&mut second, &request).await;
+            (first, response)
+        })
+    });
+
+    let mut is_pending = false;
+    for _ in 0..500 {
+        if client.is_finished() {
+            break;
+        }
+        app.update();
+        if !is_pending && app.world().get_resource::<QueryRemotePending>().is_some_and(|pending| pending.len() == 1) {
+            is_pending = true;
+            go_sender.send(()).unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let (first, second) = client.join().unwrap();
+    assert_eq!(second.uuid, uuid);
+    assert_eq!(second.error, Some(format!("Request {} is already in flight", uuid)));
+    assert_eq!(app.world().resource::<QueryRemotePending>().len(), 1);
+    drop(first);
+}
@@ -0,0 +1,74 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+use bevy_app::prelude::*;
+use bevy_query_service::*;
+
+#[derive(Clone)]
+struct Request;
+
+struct Short;
+
+struct Detailed;
+
+fn count_goals(app: &mut App) -> usize {
+    app.world_mut().query::<&GoalComponent>().iter(app.world()).count()
+}
+
+fn send(app: &mut App, event: QueryEvent<Request>) {
+    app.world_mut().send_event(event);
+    app.update();
+}
+
+fn new_app() -> App {
+    let mut app = App::new();
+    app.add_event::<QueryEvent<Request>>();
+    app.add_systems(Update, spawn_request_endpoint::<Request, Short>);
+    app
+}
+
+#[test]
+fn binds_untyped_requests_to_the_only_reply_content() {
+    let mut app = new_app();
+    send(&mut app, QueryEvent::new(uuid::Uuid::new_v4(), Request));
+    assert_eq!(count_goals(&mut app), 1);
+    assert!(app.world().resource::<QueryEndpoints<Request>>().contains::<Short>());
+}
+
+#[test]
+fn refuses_untyped_requests_with_several_reply_contents() {
+    let mut app = new_app();
+    app.add_systems(Update, spawn_request_endpoint::<Request, Detailed>);
+    send(&mut app, QueryEvent::new(uuid::Uuid::new_v4(), Request));
+    assert_eq!(count_goals(&mut app), 0);
+
+    send(&mut app, QueryEvent::new(uuid::Uuid::new_v4(), Request).with_reply::<Detailed>());
+    assert_eq!(count_goals(&mut app), 1);
+}
+
+#[test]
+fn observers_count_as_endpoints() {
+    let mut app = new_app();
+    app.add_observer(observe_request_endpoint::<Request, Detailed>);
+    app.update();
+    assert_eq!(app.world().resource::<QueryEndpoints<Request>>().len(), 2);
+
+    app.world_mut().trigger(QueryEvent::new(uuid::Uuid::new_v4(), Request));
+    app.update();
+    assert_eq!(count_goals(&mut app), 0);
+}
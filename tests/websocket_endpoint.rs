@@ -0,0 +1,121 @@
+// =========================================================================
+/*
+ * Copyright (C) 2019 Tan Jun Kiat
+ *
+ * Licensed under the Apache License, Version 2.0 (the "License");
+ * you may not use this file except in compliance with the License.
+ * You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ *
+*/
+// =========================================================================
+mod common;
+
+use bevy_app::prelude::*;
+use bevy_query_service::*;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message;
+
+/// A request without a server system, whose goals stay in flight until cancelled
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Stalled;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+struct Reply;
+
+type Socket = tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>;
+
+fn new_app() -> (App, std::net::SocketAddr) {
+    common::new_app(
+        |app| {
+            app.add_plugins(QueryWebSocketPlugin::new("127.0.0.1:0").with_service::<Stalled, Reply>("stalled"));
+            app.add_systems(Update, cleanup_requests);
+        },
+        |address: &QueryWebSocketAddress| address.0,
+    )
+}
+
+async fn connect(address: std::net::SocketAddr) -> Socket {
+    let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let (socket, _) = tokio_tungstenite::client_async(format!("ws://{}", address), stream).await.unwrap();
+    socket
+}
+
+async fn send(socket: &mut Socket, message: QueryWebSocketMessage) {
+    socket.send(Message::text(serde_json::to_string(&message).unwrap())).await.unwrap();
+}
+
+async fn receive(socket: &mut Socket) -> QueryWebSocketMessage {
+    loop {
+        if let Message::Text(text) = socket.next().await.unwrap().unwrap() {
+            return serde_json::from_str(text.as_str()).unwrap();
+        }
+    }
+}
+
+fn stalled_request(uuid: uuid::Uuid) -> QueryWebSocketMessage {
+    QueryWebSocketMessage::Request {
+        uuid,
+        service: "stalled".to_string(),
+        request: serde_json::Value::Null,
+    }
+}
+
+fn get_error(message: QueryWebSocketMessage) -> String {
+    match message {
+        QueryWebSocketMessage::Error { error, .. } => error,
+        message => panic!("Expected an error, got {:?}", message),
+    }
+}
+
+#[test]
+fn refuses_a_uuid_already_in_flight_on_the_connection() {
+    let (mut app, address) = new_app();
+    common::run_client(&mut app, move || async move {
+        let uuid = uuid::Uuid::new_v4();
+        let mut socket = connect(address).await;
+        send(&mut socket, stalled_request(uuid)).await;
+        send(&mut socket, stalled_request(uuid)).await;
+        assert_eq!(get_error(receive(&mut socket).await), format!("Request {} is already in flight", uuid));
+    });
+}
+
+#[test]
+fn only_cancels_requests_of_the_connection() {
+    let (mut app, address) = new_app();
+    common::run_client(&mut app, move || async move {
+        let uuid = uuid::Uuid::new_v4();
+        let mut owner = connect(address).await;
+        let mut other = connect(address).await;
+        send(&mut owner, stalled_request(uuid)).await;
+
+        send(&mut other, QueryWebSocketMessage::Cancel { uuid }).await;
+        assert_eq!(get_error(receive(&mut other).await), "No pending request to cancel");
+
+        // The cancel is refused until the goal of the request is spawned
+        loop {
+            send(&mut owner, QueryWebSocketMessage::Cancel { uuid }).await;
+            match receive(&mut owner).await {
+                QueryWebSocketMessage::Cancelled { uuid: cancelled } => {
+                    assert_eq!(cancelled, uuid);
+                    break;
+                }
+                message => assert_eq!(get_error(message), "No pending request to cancel"),
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        send(&mut owner, QueryWebSocketMessage::Cancel { uuid }).await;
+        assert_eq!(get_error(receive(&mut owner).await), "No pending request to cancel");
+    });
+}